@@ -0,0 +1,496 @@
+// src/build_systems/cmake_generator.rs
+use crate::build_systems::{
+    run_tool, uses_build_system, write_if_changed, BuildRequest, BuildSystem, GenerateContext,
+};
+use crate::compiler::Compiler;
+use crate::dependency_resolver::resolve_dependencies;
+use crate::hooks::hook_environment;
+use crate::project::{Project, SOURCE_EXTENSIONS};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const PROJECT_DIR: &str = "${CMAKE_CURRENT_SOURCE_DIR}";
+
+/// Writes `<project>/CMakeLists.txt` and the workspace's top-level
+/// `CMakeLists.txt`, which adds every project built with CMake.
+pub fn generate_cmake(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cmake_projects = resolve_dependencies(context.workspace)
+        .into_iter()
+        .filter(|p| uses_build_system(p, context, "CMake"))
+        .collect::<Vec<_>>();
+    fs::create_dir_all(&project.name)?;
+    write_if_changed(
+        &Path::new(&project.name).join("CMakeLists.txt"),
+        &project_lists(project, &cmake_projects, compiler)?,
+    )?;
+    write_if_changed(
+        Path::new("CMakeLists.txt"),
+        &workspace_lists(&cmake_projects)?,
+    )?;
+    Ok(())
+}
+
+/// A workspace `CMakeLists.txt` that adds each project's own.
+pub struct CMake;
+
+impl BuildSystem for CMake {
+    fn name(&self) -> &str {
+        "CMake"
+    }
+
+    fn description(&self) -> &str {
+        "CMakeLists.txt per project plus one for the workspace"
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        generate_cmake(project, context, compiler)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let configuration = request.configuration(project);
+        let build_dir = cmake_build_dir(project);
+        let mut configure = Command::new("cmake");
+        configure.args([
+            "-S",
+            ".",
+            "-B",
+            &build_dir,
+            &format!("-DCMAKE_BUILD_TYPE={}", configuration),
+        ]);
+        // The compiler can only be chosen before the first configure
+        if !Path::new(&build_dir).join("CMakeCache.txt").exists() && !request.compiler.is_msvc() {
+            let language = if project.language.starts_with("C++") {
+                "CXX"
+            } else {
+                "C"
+            };
+            configure.arg(format!(
+                "-DCMAKE_{}_COMPILER={}",
+                language, request.compiler.command
+            ));
+        }
+        run_tool(
+            &mut configure,
+            &format!("CMake configure for {}", project.name),
+        )?;
+        run_tool(
+            Command::new("cmake")
+                .args([
+                    "--build",
+                    &build_dir,
+                    "--target",
+                    &project.name,
+                    "--config",
+                    configuration,
+                ])
+                .args(["--parallel", &request.jobs.to_string()]),
+            &format!("Build of {}", project.name),
+        )
+    }
+
+    fn clean(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let build_dir = cmake_build_dir(project);
+        // Nothing was ever built without a configured tree. CMake's `clean`
+        // covers the whole tree, so this cleans every CMake project
+        if !Path::new(&build_dir).join("CMakeCache.txt").exists() {
+            return Ok(());
+        }
+        run_tool(
+            Command::new("cmake").args([
+                "--build",
+                &build_dir,
+                "--target",
+                "clean",
+                "--config",
+                request.configuration(project),
+            ]),
+            &format!("Clean of {}", project.name),
+        )
+    }
+}
+
+/// `cmake-build` in the workspace, with a subdirectory per cross target.
+pub fn cmake_build_dir(project: &Project) -> String {
+    match &project.target {
+        Some(target) => format!("cmake-build/{}", target.triple),
+        None => "cmake-build".to_string(),
+    }
+}
+
+/// The top-level file: languages, an imported target per installed package
+/// and the projects in dependency order.
+fn workspace_lists(projects: &[&Project]) -> Result<String, Box<dyn std::error::Error>> {
+    let workspace_name = std::env::current_dir()?
+        .file_name()
+        .map(|name| {
+            name.to_string_lossy()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        })
+        .unwrap_or_else(|| "workspace".to_string());
+    let mut languages = Vec::new();
+    if projects.iter().any(|p| !p.language.starts_with("C++")) {
+        languages.push("C");
+    }
+    if projects.iter().any(|p| p.language.starts_with("C++")) {
+        languages.push("CXX");
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from the workspace manifest. Do not edit."
+    )?;
+    writeln!(out, "cmake_minimum_required(VERSION 3.16)")?;
+    writeln!(
+        out,
+        "project({} LANGUAGES {})\n",
+        workspace_name,
+        languages.join(" ")
+    )?;
+
+    let mut packages = Vec::new();
+    for project in projects {
+        for name in project.dependencies.keys() {
+            if !projects.iter().any(|p| &p.name == name)
+                && !packages.contains(&(name, project.deps_dir()))
+            {
+                packages.push((name, project.deps_dir()));
+            }
+        }
+    }
+    packages.sort();
+    for (name, deps_dir) in packages {
+        write_imported_target(&mut out, name, &deps_dir)?;
+    }
+
+    for project in projects {
+        writeln!(out, "add_subdirectory({})", project.name)?;
+    }
+    Ok(out)
+}
+
+/// `cpm::<name>` for a package in `deps/`, with its library if it has one
+/// and as an interface target for header-only packages.
+fn write_imported_target(out: &mut String, name: &str, deps_dir: &str) -> std::fmt::Result {
+    let variable = format!(
+        "CPM_{}_LIBRARY",
+        name.to_ascii_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    let package_dir = format!("${{CMAKE_CURRENT_SOURCE_DIR}}/{}/{}", deps_dir, name);
+    writeln!(
+        out,
+        "find_library({} NAMES {} PATHS \"{}/lib\" NO_DEFAULT_PATH)",
+        variable, name, package_dir
+    )?;
+    writeln!(out, "if({})", variable)?;
+    writeln!(out, "  add_library(cpm::{} UNKNOWN IMPORTED GLOBAL)", name)?;
+    writeln!(
+        out,
+        "  set_target_properties(cpm::{} PROPERTIES IMPORTED_LOCATION \"${{{}}}\")",
+        name, variable
+    )?;
+    writeln!(out, "else()")?;
+    writeln!(
+        out,
+        "  add_library(cpm::{} INTERFACE IMPORTED GLOBAL)",
+        name
+    )?;
+    writeln!(out, "endif()")?;
+    writeln!(
+        out,
+        "set_target_properties(cpm::{} PROPERTIES INTERFACE_INCLUDE_DIRECTORIES \"{}/include\")\n",
+        name, package_dir
+    )
+}
+
+fn project_lists(
+    project: &Project,
+    cmake_projects: &[&Project],
+    compiler: &Compiler,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let name = &project.name;
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from the project manifest. Do not edit."
+    )?;
+
+    let patterns = SOURCE_EXTENSIONS
+        .iter()
+        .map(|extension| format!("{}/*.{}", project.structure.src_dir, extension))
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "file(GLOB {}_SOURCES CONFIGURE_DEPENDS {})",
+        name,
+        patterns.join(" ")
+    )?;
+    // Generated headers are listed too so their commands run before compiling
+    let generated = project
+        .generated_sources()
+        .into_iter()
+        .chain(project.generated_headers())
+        .map(|path| quote(&format!("{}/{}", PROJECT_DIR, path)))
+        .collect::<Vec<_>>();
+    let sources = std::iter::once(format!("${{{}_SOURCES}}", name))
+        .chain(generated)
+        .collect::<Vec<_>>();
+    match project.project_type.as_str() {
+        "StaticLib" => writeln!(out, "add_library({} STATIC {})", name, sources.join(" "))?,
+        "SharedLib" => writeln!(out, "add_library({} SHARED {})", name, sources.join(" "))?,
+        "Windowed" => writeln!(out, "add_executable({} WIN32 {})", name, sources.join(" "))?,
+        _ => writeln!(out, "add_executable({} {})", name, sources.join(" "))?,
+    }
+
+    // A generator expression keeps multi-config generators from appending
+    // the configuration, so outputs land where the other backends put them
+    let output_dir = quote(&format!("{}/{}$<0:>", PROJECT_DIR, project.output_dir()));
+    let mut properties = vec![
+        ("RUNTIME_OUTPUT_DIRECTORY", output_dir.clone()),
+        ("LIBRARY_OUTPUT_DIRECTORY", output_dir.clone()),
+        ("ARCHIVE_OUTPUT_DIRECTORY", output_dir),
+    ];
+    if let Some(output_name) = &project.output_name {
+        properties.push(("OUTPUT_NAME", quote(output_name)));
+    }
+    if let (Some(version), "SharedLib") =
+        (project.semantic_version(), project.project_type.as_str())
+    {
+        properties.push((
+            "VERSION",
+            format!("{}.{}.{}", version.major, version.minor, version.patch),
+        ));
+        properties.push(("SOVERSION", version.major.to_string()));
+    }
+    writeln!(out, "set_target_properties({} PROPERTIES", name)?;
+    for (property, value) in properties {
+        writeln!(out, "  {} {}", property, value)?;
+    }
+    writeln!(out, ")")?;
+
+    let visibility = if project.is_library() {
+        "PUBLIC"
+    } else {
+        "PRIVATE"
+    };
+    let mut include_dirs = format!("PUBLIC {}", quote(&project.structure.include_dir));
+    let private_dirs = project
+        .additional_include_dirs
+        .iter()
+        .cloned()
+        .chain(project.generated_include_dirs())
+        .map(|dir| quote(&dir))
+        .collect::<Vec<_>>();
+    if !private_dirs.is_empty() {
+        include_dirs.push_str(&format!(" PRIVATE {}", private_dirs.join(" ")));
+    }
+    writeln!(out, "target_include_directories({} {})", name, include_dirs)?;
+    if !project.preprocessor_definitions.is_empty() {
+        let definitions = project
+            .preprocessor_definitions
+            .iter()
+            .map(|d| quote(d))
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "target_compile_definitions({} PRIVATE {})",
+            name,
+            definitions.join(" ")
+        )?;
+    }
+
+    // The same options as the other backends, chosen per configuration
+    for configuration in &project.configurations {
+        let compile_flags = compiler.compile_flags(project, configuration);
+        if !compile_flags.is_empty() {
+            writeln!(
+                out,
+                "target_compile_options({} PRIVATE {})",
+                name,
+                for_configuration(configuration, &compile_flags)
+            )?;
+        }
+        let link_flags = compiler.link_flags(project, configuration);
+        if !link_flags.is_empty() && project.project_type != "StaticLib" {
+            writeln!(
+                out,
+                "target_link_options({} PRIVATE {})",
+                name,
+                for_configuration(configuration, &link_flags)
+            )?;
+        }
+    }
+
+    let mut dependencies = project.dependencies.keys().collect::<Vec<_>>();
+    dependencies.sort();
+    let libraries = dependencies
+        .into_iter()
+        .map(
+            |dependency| match cmake_projects.iter().any(|p| &p.name == dependency) {
+                true => dependency.clone(),
+                false => format!("cpm::{}", dependency),
+            },
+        )
+        .collect::<Vec<_>>();
+    if !libraries.is_empty() {
+        writeln!(
+            out,
+            "target_link_libraries({} {} {})",
+            name,
+            visibility,
+            libraries.join(" ")
+        )?;
+    }
+
+    for custom in &project.custom_commands {
+        if custom.outputs.is_empty() {
+            continue;
+        }
+        let outputs = custom
+            .outputs
+            .iter()
+            .map(|o| quote(&format!("{}/{}", PROJECT_DIR, o)));
+        let inputs = custom
+            .inputs
+            .iter()
+            .map(|i| quote(&format!("{}/{}", PROJECT_DIR, i)));
+        writeln!(out, "add_custom_command(")?;
+        writeln!(out, "  OUTPUT {}", outputs.collect::<Vec<_>>().join(" "))?;
+        writeln!(
+            out,
+            "  COMMAND {}",
+            shell_command(&custom.expanded_command()).join(" ")
+        )?;
+        if !custom.inputs.is_empty() {
+            writeln!(out, "  DEPENDS {}", inputs.collect::<Vec<_>>().join(" "))?;
+        }
+        writeln!(out, "  WORKING_DIRECTORY {}", quote(PROJECT_DIR))?;
+        writeln!(out, "  COMMENT {}", quote(&custom.message()))?;
+        writeln!(out, "  VERBATIM)")?;
+    }
+
+    // PRE_BUILD only runs before compiling with Visual Studio; other
+    // generators run it just before linking
+    write_hooks(&mut out, project, "PRE_BUILD", &project.pre_build, |s| {
+        &s.pre_build
+    })?;
+    write_hooks(&mut out, project, "POST_BUILD", &project.post_build, |s| {
+        &s.post_build
+    })?;
+    Ok(out)
+}
+
+/// One `add_custom_command(TARGET ...)` for the project-wide hooks, with the
+/// per-configuration ones behind generator expressions that are empty, and
+/// skipped, in other configurations.
+fn write_hooks(
+    out: &mut String,
+    project: &Project,
+    when: &str,
+    common: &[String],
+    per_configuration: fn(&crate::project::ConfigurationSettings) -> &Vec<String>,
+) -> std::fmt::Result {
+    let mut commands = Vec::new();
+    for command in common {
+        let arguments = hook_command(project, command, "$<CONFIG>");
+        commands.push(
+            arguments
+                .iter()
+                .map(|a| quote(a))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    for configuration in &project.configurations {
+        let extra = match project.per_configuration.get(configuration) {
+            Some(settings) => per_configuration(settings),
+            None => continue,
+        };
+        for command in extra {
+            let arguments = hook_command(project, command, configuration);
+            let list = arguments
+                .iter()
+                .map(|a| genex_escape(a))
+                .collect::<Vec<_>>()
+                .join(";");
+            commands.push(quote(&format!("$<$<CONFIG:{}>:{}>", configuration, list)));
+        }
+    }
+    if commands.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "add_custom_command(TARGET {} {}", project.name, when)?;
+    for command in commands {
+        writeln!(out, "  COMMAND {}", command)?;
+    }
+    writeln!(out, "  WORKING_DIRECTORY {}", quote(PROJECT_DIR))?;
+    writeln!(out, "  COMMAND_EXPAND_LISTS VERBATIM)")
+}
+
+/// A hook run through `cmake -E env` so it sees the `CPM_*` variables.
+fn hook_command(project: &Project, command: &str, configuration: &str) -> Vec<String> {
+    let mut arguments = vec![
+        "${CMAKE_COMMAND}".to_string(),
+        "-E".to_string(),
+        "env".to_string(),
+    ];
+    for (variable, value) in hook_environment(project, PROJECT_DIR, configuration, '/') {
+        arguments.push(format!("{}={}", variable, value));
+    }
+    arguments.extend(shell_arguments(command));
+    arguments
+}
+
+fn shell_command(command: &str) -> Vec<String> {
+    shell_arguments(command).iter().map(|a| quote(a)).collect()
+}
+
+fn shell_arguments(command: &str) -> Vec<String> {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    vec![program.to_string(), flag.to_string(), command.to_string()]
+}
+
+/// `flags` as a generator expression that only applies in `configuration`.
+fn for_configuration(configuration: &str, flags: &[String]) -> String {
+    let list = flags
+        .iter()
+        .map(|f| genex_escape(f))
+        .collect::<Vec<_>>()
+        .join(";");
+    quote(&format!("$<$<CONFIG:{}>:{}>", configuration, list))
+}
+
+fn genex_escape(text: &str) -> String {
+    text.replace('>', "$<ANGLE-R>")
+        .replace(',', "$<COMMA>")
+        .replace(';', "$<SEMICOLON>")
+}
+
+/// A quoted CMake argument. `${...}` references are left to expand.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -1,59 +1,325 @@
-use crate::project::Project;
+use crate::build_systems::native::{artifact_path, include_dirs, link_library, soname};
+use crate::build_systems::{
+    output_dirs, run_tool, shell_quote, BuildRequest, BuildSystem, GenerateContext,
+};
+use crate::compilation_database::CompileCommand;
+use crate::compiler::Compiler;
+use crate::hooks::hook_environment;
+use crate::project::{ConfigurationSettings, Project};
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
+use std::process::Command;
 
-pub fn generate_makefile(project: &Project) -> std::io::Result<()> {
+const SOURCE_EXTENSIONS: [&str; 4] = ["c", "cc", "cpp", "cxx"];
+
+/// A Makefile per project, built with `make CONFIG=<configuration>`.
+pub struct Makefile;
+
+impl BuildSystem for Makefile {
+    fn name(&self) -> &str {
+        "Makefile"
+    }
+
+    fn description(&self) -> &str {
+        "GNU Makefile per project, built with make"
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(generate_makefile(project, context.workspace, compiler)?)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut command = make(project);
+        if let Some(configuration) = request.configuration {
+            command.arg(format!("CONFIG={}", configuration));
+        }
+        run_tool(&mut command, &format!("Build of {}", project.name))
+    }
+
+    fn clean(
+        &self,
+        project: &Project,
+        _request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        run_tool(
+            make(project).arg("clean"),
+            &format!("Clean of {}", project.name),
+        )
+    }
+
+    /// The pattern rule's recipe with the variables for `configuration`.
+    fn compile_command(
+        &self,
+        project: &Project,
+        workspace: &[Project],
+        compiler: &Compiler,
+        configuration: &str,
+        source: &str,
+    ) -> CompileCommand {
+        let stem = Path::new(source).with_extension("");
+        let object = format!("{}/{}.o", object_dir(project), stem.to_string_lossy());
+        let mut command = vec![compiler.command.clone()];
+        command.extend(compiler.compile_flags(project, configuration));
+        command.extend(definition_flags(project));
+        command.extend(include_flags(project, workspace));
+        command.extend([
+            "-c".to_string(),
+            "-o".to_string(),
+            object.clone(),
+            source.to_string(),
+        ]);
+        CompileCommand::new(Path::new(&project.name), command, source, &object)
+    }
+}
+
+fn make(project: &Project) -> Command {
+    let mut command = Command::new("make");
+    command
+        .current_dir(&project.name)
+        .args(["-f", &makefile_name(project)]);
+    command
+}
+
+pub fn generate_makefile(
+    project: &Project,
+    workspace: &[Project],
+    compiler: &Compiler,
+) -> std::io::Result<()> {
     let project_dir = &project.name;
     fs::create_dir_all(project_dir)?;
 
     // Create project structure directories
     let src_path = Path::new(project_dir).join(&project.structure.src_dir);
     let include_path = Path::new(project_dir).join(&project.structure.include_dir);
-    let output_path = Path::new(project_dir).join(&project.structure.output_dir);
+    let output_dir = project.output_dir();
+    let output_path = Path::new(project_dir).join(&output_dir);
 
     fs::create_dir_all(&src_path)?;
     fs::create_dir_all(&include_path)?;
     fs::create_dir_all(&output_path)?;
 
-    let file_name = format!("{}/Makefile", project_dir);
+    let file_name = format!("{}/{}", project_dir, makefile_name(project));
     let mut file = File::create(&file_name)?;
 
-    // Map language standard
-    let language_standard = map_language_standard(&project.language);
+    // Configuration selected with `make CONFIG=Release`, defaulting to the first one
+    writeln!(file, "CONFIG ?= {}", project.default_configuration())?;
+
+    // Variables visible to pre/post-build commands
+    for (name, value) in hook_environment(project, "$(CURDIR)", "$(CONFIG)", '/') {
+        writeln!(file, "export {} := {}", name, value)?;
+    }
 
-    // Collect source files based on custom src_dir
-    writeln!(file, "CC={}", project.compiler)?;
-    writeln!(file, "CFLAGS=-std={} -Wall -O2", language_standard)?;
+    // The detected compiler, by full path, so the Makefile doesn't depend on PATH
+    writeln!(file, "CC={}", compiler.command)?;
+    writeln!(file, "AR={}", compiler.archiver)?;
+    // Flags follow the configuration, so each one gets its own block
+    for configuration in &project.configurations {
+        writeln!(file, "ifeq ($(CONFIG),{})", configuration)?;
+        writeln!(
+            file,
+            "CFLAGS={}",
+            compiler.compile_flags(project, configuration).join(" ")
+        )?;
+        writeln!(
+            file,
+            "LDFLAGS={}",
+            compiler.link_flags(project, configuration).join(" ")
+        )?;
+        writeln!(file, "endif")?;
+    }
 
+    // Collect source files based on custom src_dir. Generated sources may also
+    // match the wildcard once they exist; sort drops duplicates
+    let wildcards = SOURCE_EXTENSIONS
+        .iter()
+        .map(|extension| format!("{}/*.{}", project.structure.src_dir, extension))
+        .collect::<Vec<_>>();
+    writeln!(
+        file,
+        "SRCS=$(sort $(wildcard {}) {})",
+        wildcards.join(" "),
+        project.generated_sources().join(" ")
+    )?;
+    writeln!(file, "OBJDIR={}", object_dir(project))?;
+    writeln!(
+        file,
+        "OBJS=$(addprefix $(OBJDIR)/,$(addsuffix .o,$(basename $(SRCS))))"
+    )?;
+    writeln!(file, "GENERATED={}", generated_outputs(project).join(" "))?;
     writeln!(
         file,
-        "SRCS=$(wildcard {}/{}/*.c)",
-        project.name, project.structure.src_dir
+        "GENERATED_HEADERS={}",
+        project.generated_headers().join(" ")
     )?;
-    writeln!(file, "OBJS=$(SRCS:.c=.o)")?;
 
     writeln!(file, "LIBS={}", format_dependencies(&project.dependencies))?;
 
-    writeln!(file, "INCLUDES={}", format_include_paths(project))?;
+    // The same include directories and definitions as every other backend
+    writeln!(file, "DEFINES={}", definition_flags(project).join(" "))?;
+    writeln!(
+        file,
+        "INCLUDES={}",
+        include_flags(project, workspace).join(" ")
+    )?;
     writeln!(file, "LIBPATHS={}", format_lib_paths(project))?;
 
-    writeln!(file, "TARGET={}/{}", project.structure.output_dir, project.name)?;
+    // Outputs are named as the Native backend names them. Versioned shared
+    // libraries follow the ELF convention: libx.so.1.2.3 with a soname of
+    // libx.so.1 and symlinks for the runtime and link-time names
+    let target = artifact_path(project, compiler);
+    writeln!(file, "TARGET={}", target)?;
+    let soname = soname(project, compiler);
+    let mut links = Vec::new();
+    if let Some(soname) = &soname {
+        links.push(format!("{}/{}", output_dir, soname));
+        links.extend(link_library(project, compiler));
+    }
+    let soname_flag = match &soname {
+        Some(soname) => format!(" -Wl,-soname,{}", soname),
+        // Dependents would otherwise record the path they linked it by
+        None if project.project_type == "SharedLib" && target.ends_with(".so") => {
+            " -Wl,-soname,$(notdir $(TARGET))".to_string()
+        }
+        None => String::new(),
+    };
+
+    writeln!(file, ".PHONY: all clean prebuild")?;
 
-    // Create output directory command
+    // Post-build commands run after the target is up to date, every time
     writeln!(file, "all: $(TARGET)")?;
+    write_hook_commands(&mut file, project, &project.post_build, |s| &s.post_build)?;
+
+    // Objects wait on the pre-build step without being rebuilt because of it
+    writeln!(file, "prebuild:")?;
+    write_hook_commands(&mut file, project, &project.pre_build, |s| &s.pre_build)?;
+    writeln!(file, "$(OBJS): $(GENERATED_HEADERS) | prebuild")?;
+
+    for extension in SOURCE_EXTENSIONS {
+        writeln!(file, "$(OBJDIR)/%.o: %.{}", extension)?;
+        writeln!(file, "\t@mkdir -p $(@D)")?;
+        writeln!(file, "\t$(CC) $(CFLAGS) $(DEFINES) $(INCLUDES) -c -o $@ $<")?;
+    }
+
+    write_custom_commands(&mut file, project)?;
+
+    // Create output directory command
     writeln!(file, "$(TARGET): $(OBJS)")?;
-    writeln!(file, "\tmkdir -p {}", project.structure.output_dir)?;
+    writeln!(file, "\tmkdir -p {}", output_dir)?;
     if project.project_type == "StaticLib" {
-        writeln!(file, "\tar rcs $@ $^")?;
+        writeln!(file, "\t$(AR) rcs $@ $^")?;
     } else if project.project_type == "SharedLib" {
-        writeln!(file, "\t$(CC) $(CFLAGS) $(INCLUDES) $(LIBPATHS) -shared -o $@ $^ $(LIBS)")?;
+        writeln!(
+            file,
+            "\t$(CC) $(CFLAGS) $(DEFINES) $(INCLUDES) $(LIBPATHS) $(LDFLAGS) -shared{} -o $@ $^ $(LIBS)",
+            soname_flag
+        )?;
+        if let (Some(soname), [soname_link, library_link]) = (&soname, links.as_slice()) {
+            writeln!(file, "\tln -sf $(notdir $(TARGET)) {}", soname_link)?;
+            writeln!(file, "\tln -sf {} {}", soname, library_link)?;
+        }
     } else {
-        writeln!(file, "\t$(CC) $(CFLAGS) $(INCLUDES) $(LIBPATHS) -o $@ $^ $(LIBS)")?;
+        writeln!(
+            file,
+            "\t$(CC) $(CFLAGS) $(DEFINES) $(INCLUDES) $(LIBPATHS) $(LDFLAGS) -o $@ $^ $(LIBS)"
+        )?;
     }
     writeln!(file, "clean:")?;
-    writeln!(file, "\trm -f $(OBJS) $(TARGET)")?;
+    writeln!(file, "\trm -rf $(OBJDIR)")?;
+    writeln!(file, "\trm -f $(TARGET) $(GENERATED) {}", links.join(" "))?;
+
+    Ok(())
+}
+
+/// `Makefile` for the host and `Makefile.<triple>` per cross target, so
+/// generating for one target leaves the others in place.
+pub fn makefile_name(project: &Project) -> String {
+    match &project.target {
+        Some(target) => format!("Makefile.{}", target.triple),
+        None => "Makefile".to_string(),
+    }
+}
 
+/// One rule per custom command. The first output carries the recipe and the
+/// others depend on it, which works without grouped targets (GNU make 4.3).
+fn write_custom_commands(file: &mut File, project: &Project) -> std::io::Result<()> {
+    for custom in &project.custom_commands {
+        let (primary, others) = match custom.outputs.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        let inputs = custom
+            .inputs
+            .iter()
+            .map(|input| make_path(input))
+            .collect::<Vec<_>>();
+        writeln!(file, "{}: {}", make_path(primary), inputs.join(" "))?;
+        writeln!(
+            file,
+            "\t@echo {}",
+            shell_quote(&custom.message()).replace('$', "$$")
+        )?;
+        for dir in output_dirs(&custom.outputs) {
+            writeln!(file, "\tmkdir -p {}", shell_quote(&dir).replace('$', "$$"))?;
+        }
+        writeln!(file, "\t{}", custom.expanded_command().replace('$', "$$"))?;
+        for other in others {
+            writeln!(file, "{}: {} ;", make_path(other), make_path(primary))?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` as a target or prerequisite, where make splits on spaces and expands `$`.
+fn make_path(path: &str) -> String {
+    path.replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(' ', "\\ ")
+}
+
+fn generated_outputs(project: &Project) -> Vec<&str> {
+    project
+        .custom_commands
+        .iter()
+        .flat_map(|c| &c.outputs)
+        .map(String::as_str)
+        .collect()
+}
+
+/// Writes the project-wide commands followed by one `ifeq` block per
+/// configuration that adds its own.
+fn write_hook_commands(
+    file: &mut File,
+    project: &Project,
+    common: &[String],
+    per_configuration: fn(&ConfigurationSettings) -> &Vec<String>,
+) -> std::io::Result<()> {
+    // Commands are shell commands; keep make from expanding their `$VARIABLES`
+    for command in common {
+        writeln!(file, "\t{}", command.replace('$', "$$"))?;
+    }
+    for configuration in &project.configurations {
+        let extra = match project.per_configuration.get(configuration) {
+            Some(settings) if !per_configuration(settings).is_empty() => {
+                per_configuration(settings)
+            }
+            _ => continue,
+        };
+        writeln!(file, "ifeq ($(CONFIG),{})", configuration)?;
+        for command in extra {
+            writeln!(file, "\t{}", command.replace('$', "$$"))?;
+        }
+        writeln!(file, "endif")?;
+    }
     Ok(())
 }
 
@@ -65,36 +331,32 @@ fn format_dependencies(dependencies: &std::collections::HashMap<String, String>)
         .join(" ")
 }
 
-fn format_include_paths(project: &Project) -> String {
-    let mut include_paths = Vec::new();
-    include_paths.push(format!("-I{}/{}", project.name, project.structure.include_dir));
-    for dep_name in project.dependencies.keys() {
-        include_paths.push(format!("-Ideps/{}/include", dep_name));
-    }
-    include_paths.join(" ")
+/// Objects go under the output directory so each target keeps its own, apart
+/// from those of the Native backend, which compiles with other flags.
+fn object_dir(project: &Project) -> String {
+    format!("{}/make-obj", project.output_dir())
+}
+
+fn definition_flags(project: &Project) -> Vec<String> {
+    project
+        .preprocessor_definitions
+        .iter()
+        .map(|d| format!("-D{}", d))
+        .collect()
+}
+
+fn include_flags(project: &Project, workspace: &[Project]) -> Vec<String> {
+    include_dirs(project, workspace)
+        .iter()
+        .map(|dir| format!("-I{}", dir))
+        .collect()
 }
 
 fn format_lib_paths(project: &Project) -> String {
     let mut lib_paths = Vec::new();
-    lib_paths.push(format!("-L{}/lib", project.name));
+    lib_paths.push("-Llib".to_string());
     for dep_name in project.dependencies.keys() {
-        lib_paths.push(format!("-Ldeps/{}/lib", dep_name));
+        lib_paths.push(format!("-L../{}/{}/lib", project.deps_dir(), dep_name));
     }
     lib_paths.join(" ")
 }
-
-fn map_language_standard(language: &str) -> &str {
-    match language {
-        "C89" => "c89",
-        "C99" => "c99",
-        "C11" => "c11",
-        "C17" => "c17",
-        "C++98" => "c++98",
-        "C++11" => "c++11",
-        "C++14" => "c++14",
-        "C++17" => "c++17",
-        "C++20" => "c++20",
-        "C++23" => "c++23",
-        _ => "c11", // Default to C11
-    }
-}
@@ -0,0 +1,610 @@
+// src/build_systems/meson_generator.rs
+use crate::build_systems::native::sources;
+use crate::build_systems::{
+    output_dirs, run_tool, shell_join, shell_quote, uses_build_system, write_if_changed,
+    BuildRequest, BuildSystem, GenerateContext,
+};
+use crate::compiler::Compiler;
+use crate::dependency_resolver::resolve_dependencies;
+use crate::hooks::run_hooks;
+use crate::project::Project;
+use log::warn;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A workspace `meson.build` that adds each project's own as a subdirectory.
+pub struct Meson;
+
+impl BuildSystem for Meson {
+    fn name(&self) -> &str {
+        "Meson"
+    }
+
+    fn description(&self) -> &str {
+        "meson.build per project plus one for the workspace"
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        generate_meson(project, context, compiler)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let configuration = request.configuration(project);
+        let build_dir = meson_build_dir(project);
+        let option = format!("-Dconfiguration={}", configuration);
+        let mut setup = Command::new("meson");
+        if Path::new(&build_dir).join("meson-private").exists() {
+            setup.args(["configure", &build_dir, &option]);
+        } else {
+            // The compiler can only be chosen when the directory is set up
+            let variable = if project.language.starts_with("C++") {
+                "CXX"
+            } else {
+                "CC"
+            };
+            setup
+                .args(["setup", &build_dir, &option])
+                .env(variable, &request.compiler.command);
+        }
+        run_tool(&mut setup, &format!("Meson setup for {}", project.name))?;
+        // Meson has no build events, so hooks run around the compile
+        let project_dir = fs::canonicalize(&project.name)?;
+        run_hooks(
+            project,
+            &project.pre_build_commands(configuration),
+            &project_dir,
+            configuration,
+        )?;
+        run_tool(
+            Command::new("meson")
+                .args(["compile", "-C", &build_dir, meson_target_name(project)])
+                .args(["-j", &request.jobs.to_string()]),
+            &format!("Build of {}", project.name),
+        )?;
+        run_hooks(
+            project,
+            &project.post_build_commands(configuration),
+            &project_dir,
+            configuration,
+        )
+    }
+
+    // Meson only cleans whole build directories
+    fn clean(
+        &self,
+        project: &Project,
+        _request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let build_dir = meson_build_dir(project);
+        if !Path::new(&build_dir).join("meson-private").exists() {
+            return Ok(());
+        }
+        run_tool(
+            Command::new("meson").args(["compile", "-C", &build_dir, "--clean"]),
+            &format!("Clean of {}", project.name),
+        )
+    }
+
+    fn output_dir(&self, project: &Project, _request: &BuildRequest) -> String {
+        format!("{}/{}", meson_build_dir(project), project.name)
+    }
+}
+
+/// Writes `<project>/meson.build` plus the workspace's top-level
+/// `meson.build` and `meson_options.txt`, which cover every Meson project.
+pub fn generate_meson(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meson_projects = resolve_dependencies(context.workspace)
+        .into_iter()
+        .filter(|p| uses_build_system(p, context, "Meson"))
+        .collect::<Vec<_>>();
+    let mut dependencies = project.dependencies.keys().collect::<Vec<_>>();
+    dependencies.sort();
+    for name in dependencies {
+        if context.workspace.iter().any(|p| &p.name == name)
+            && !meson_projects.iter().any(|p| &p.name == name)
+        {
+            warn!(
+                "{}: {} isn't built with Meson, so meson.build neither builds nor links it",
+                project.name, name
+            );
+        }
+    }
+    // Meson refuses include directories that don't exist
+    fs::create_dir_all(Path::new(&project.name).join(&project.structure.include_dir))?;
+    write_if_changed(
+        &Path::new(&project.name).join("meson.build"),
+        &project_build(project, compiler, context.workspace, &meson_projects)?,
+    )?;
+    write_if_changed(
+        Path::new("meson.build"),
+        &workspace_build(context.workspace, &meson_projects)?,
+    )?;
+    write_if_changed(Path::new("meson_options.txt"), &options(&meson_projects)?)?;
+    Ok(())
+}
+
+/// `meson-build` in the workspace, with a subdirectory per cross target.
+pub fn meson_build_dir(project: &Project) -> String {
+    match &project.target {
+        Some(target) => format!("meson-build/{}", target.triple),
+        None => "meson-build".to_string(),
+    }
+}
+
+/// The Meson target for the project, which is named after its output.
+pub fn meson_target_name(project: &Project) -> &str {
+    project.output_stem()
+}
+
+/// The top-level file: languages, a dependency object per installed package
+/// and the projects in dependency order. cpm's flags replace Meson's own
+/// build types and warning levels.
+fn workspace_build(
+    workspace: &[Project],
+    projects: &[&Project],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let workspace_name = std::env::current_dir()?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "workspace".to_string());
+    let mut languages = Vec::new();
+    if projects.iter().any(|p| !p.language.starts_with("C++")) {
+        languages.push(quote("c"));
+    }
+    if projects.iter().any(|p| p.language.starts_with("C++")) {
+        languages.push(quote("cpp"));
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from the workspace manifest. Do not edit."
+    )?;
+    writeln!(
+        out,
+        "project({}, [{}], meson_version: '>=0.56.0', default_options: ['buildtype=plain', 'warning_level=0'])\n",
+        quote(&workspace_name),
+        languages.join(", ")
+    )?;
+
+    let mut packages = Vec::new();
+    for project in projects {
+        for name in project.dependencies.keys() {
+            if !workspace.iter().any(|p| &p.name == name)
+                && !packages.contains(&(name, project.deps_dir(), language_key(project)))
+            {
+                packages.push((name, project.deps_dir(), language_key(project)));
+            }
+        }
+    }
+    packages.sort();
+    for (name, deps_dir, language) in packages {
+        let package_dir = format!("{}/{}", deps_dir, name);
+        writeln!(out, "{}_dep = declare_dependency(", identifier(name))?;
+        writeln!(
+            out,
+            "  include_directories: include_directories({}),",
+            quote(&format!("{}/include", package_dir))
+        )?;
+        // Header-only packages have no library to link
+        writeln!(
+            out,
+            "  dependencies: meson.get_compiler({}).find_library({}, dirs: meson.current_source_dir() / {}, required: false),",
+            quote(language),
+            quote(name),
+            quote(&format!("{}/lib", package_dir))
+        )?;
+        writeln!(out, ")\n")?;
+    }
+
+    for project in projects {
+        writeln!(out, "subdir({})", quote(&project.name))?;
+    }
+    Ok(out)
+}
+
+/// A `configuration` option choosing between the manifest's configurations.
+fn options(projects: &[&Project]) -> Result<String, std::fmt::Error> {
+    let mut configurations = Vec::new();
+    for configuration in projects.iter().flat_map(|p| &p.configurations) {
+        if !configurations.contains(configuration) {
+            configurations.push(configuration.clone());
+        }
+    }
+    if configurations.is_empty() {
+        configurations.push("Debug".to_string());
+    }
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from the workspace manifest. Do not edit."
+    )?;
+    writeln!(
+        out,
+        "option('configuration', type: 'combo', choices: [{}], value: {}, description: 'cpm configuration to build')",
+        configurations.iter().map(|c| quote(c)).collect::<Vec<_>>().join(", "),
+        quote(&configurations[0])
+    )?;
+    Ok(out)
+}
+
+fn project_build(
+    project: &Project,
+    compiler: &Compiler,
+    workspace: &[Project],
+    meson_projects: &[&Project],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let id = identifier(&project.name);
+    let language = language_key(project);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from the project manifest. Do not edit."
+    )?;
+
+    // Meson has no globs, so sources are listed as they are now; `cpm build`
+    // regenerates this file before every build
+    let generated_sources = project.generated_sources();
+    let files = sources(project, Path::new(&project.name))
+        .into_iter()
+        .filter(|source| !generated_sources.contains(&source.as_str()))
+        .map(|source| quote(&source))
+        .collect::<Vec<_>>();
+    writeln!(out, "{}_sources = files({})", id, files.join(", "))?;
+
+    // The standard is set through `{c,cpp}_std`, so its flag is left out
+    let standard_flag = compiler.standard_flag(&project.language);
+    for (index, configuration) in project.configurations.iter().enumerate() {
+        let compile_flags = compiler
+            .compile_flags(project, configuration)
+            .into_iter()
+            .filter(|flag| Some(flag) != standard_flag.as_ref())
+            .collect::<Vec<_>>();
+        let keyword = if index == 0 { "if" } else { "elif" };
+        writeln!(
+            out,
+            "{} get_option('configuration') == {}",
+            keyword,
+            quote(configuration)
+        )?;
+        writeln!(out, "  {}_args = [{}]", id, quoted_list(&compile_flags))?;
+        writeln!(
+            out,
+            "  {}_link_args = [{}]",
+            id,
+            quoted_list(&compiler.link_flags(project, configuration))
+        )?;
+    }
+    if !project.configurations.is_empty() {
+        writeln!(out, "else")?;
+    }
+    let indent = if project.configurations.is_empty() {
+        ""
+    } else {
+        "  "
+    };
+    writeln!(out, "{}{}_args = []", indent, id)?;
+    writeln!(out, "{}{}_link_args = []", indent, id)?;
+    if !project.configurations.is_empty() {
+        writeln!(out, "endif")?;
+    }
+
+    // Custom commands run in the project directory, and their outputs are
+    // copied to the build directory, the only place Meson lets targets write
+    let mut generated = Vec::new();
+    for (index, custom) in project.custom_commands.iter().enumerate() {
+        if custom.outputs.is_empty() {
+            continue;
+        }
+        let mut script = output_dirs(&custom.outputs)
+            .iter()
+            .map(|dir| format!("mkdir -p {}", shell_quote(dir)))
+            .collect::<Vec<_>>();
+        script.insert(0, "cd \"$1\"".to_string());
+        script.push(custom.expanded_command());
+        script.push(format!("cp {} \"$2\"", shell_join(&custom.outputs)));
+        let outputs = custom
+            .outputs
+            .iter()
+            .map(|o| {
+                quote(
+                    &Path::new(o)
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "{0}_generated_{1} = custom_target('{0}_generated_{1}',",
+            id, index
+        )?;
+        writeln!(out, "  input: files({}),", quoted_list(&custom.inputs))?;
+        writeln!(out, "  output: [{}],", outputs.join(", "))?;
+        writeln!(
+            out,
+            "  command: ['sh', '-c', {}, 'sh', meson.current_source_dir(), '@OUTDIR@'],",
+            quote(&script.join(" && "))
+        )?;
+        writeln!(out, ")")?;
+        generated.push(format!("{}_generated_{}", id, index));
+    }
+
+    // Custom command headers reach the compiler through the build directory;
+    // only directories that already exist can be named here
+    let mut include_dirs = vec![project.structure.include_dir.clone()];
+    for dir in project
+        .additional_include_dirs
+        .iter()
+        .cloned()
+        .chain(project.generated_include_dirs())
+    {
+        if Path::new(&project.name).join(&dir).is_dir() && !include_dirs.contains(&dir) {
+            include_dirs.push(dir);
+        }
+    }
+    let definitions = project
+        .preprocessor_definitions
+        .iter()
+        .map(|d| format!("-D{}", d))
+        .collect::<Vec<_>>();
+    let mut dependencies = project
+        .dependencies
+        .keys()
+        // Workspace projects built some other way aren't visible to Meson;
+        // generate_meson warns about them
+        .filter(|name| {
+            meson_projects.iter().any(|p| &p.name == *name)
+                || !workspace.iter().any(|p| &p.name == *name)
+        })
+        .map(|name| format!("{}_dep", identifier(name)))
+        .collect::<Vec<_>>();
+    dependencies.sort();
+
+    let function = match project.project_type.as_str() {
+        "StaticLib" => "static_library",
+        "SharedLib" => "shared_library",
+        _ => "executable",
+    };
+    let mut sources = vec![format!("{}_sources", id)];
+    sources.extend(generated);
+    writeln!(
+        out,
+        "{}_target = {}({}, {},",
+        id,
+        function,
+        quote(meson_target_name(project)),
+        sources.join(", ")
+    )?;
+    writeln!(
+        out,
+        "  include_directories: include_directories({}),",
+        quoted_list(&include_dirs)
+    )?;
+    if definitions.is_empty() {
+        writeln!(out, "  {}_args: {}_args,", language, id)?;
+    } else {
+        writeln!(
+            out,
+            "  {}_args: {}_args + [{}],",
+            language,
+            id,
+            quoted_list(&definitions)
+        )?;
+    }
+    writeln!(out, "  link_args: {}_link_args,", id)?;
+    if project.project_type == "Windowed" {
+        writeln!(out, "  win_subsystem: 'windows',")?;
+    }
+    writeln!(out, "  dependencies: [{}],", dependencies.join(", "))?;
+    if let Some(standard) = meson_standard(&project.language) {
+        writeln!(
+            out,
+            "  override_options: [{}],",
+            quote(&format!("{}_std={}", language, standard))
+        )?;
+    }
+    if let (Some(version), "SharedLib") =
+        (project.semantic_version(), project.project_type.as_str())
+    {
+        writeln!(
+            out,
+            "  version: '{}.{}.{}',",
+            version.major, version.minor, version.patch
+        )?;
+        writeln!(out, "  soversion: '{}',", version.major)?;
+    }
+    writeln!(out, ")")?;
+
+    if project.is_library() {
+        writeln!(
+            out,
+            "{}_dep = declare_dependency(link_with: {}_target, include_directories: include_directories({}))",
+            id,
+            id,
+            quote(&project.structure.include_dir)
+        )?;
+    }
+    Ok(out)
+}
+
+fn language_key(project: &Project) -> &'static str {
+    if project.language.starts_with("C++") {
+        "cpp"
+    } else {
+        "c"
+    }
+}
+
+/// `c11`, `c++17` and so on, as Meson spells them.
+fn meson_standard(language: &str) -> Option<String> {
+    match language.to_ascii_lowercase().as_str() {
+        "c" | "c++" => None,
+        standard => Some(standard.to_string()),
+    }
+}
+
+/// A Meson variable name for a project or package.
+fn identifier(name: &str) -> String {
+    let id = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    if id.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", id)
+    } else {
+        id
+    }
+}
+
+fn quoted_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| quote(item))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectsFile;
+
+    const MANIFEST: &str = r#"
+        [[projects]]
+        name = "app"
+        type = "Console"
+        compiler = "gcc"
+        language = "C11"
+        configurations = ["Debug", "Release"]
+        preprocessor_definitions = ["APP=1"]
+        dependencies = { core = "*", zlib = "1.3", tool = "*" }
+
+        [[projects]]
+        name = "libs/core"
+        type = "StaticLib"
+        compiler = "gcc"
+        language = "C11"
+        configurations = ["Debug", "Release", "Profile"]
+
+        [[projects]]
+        name = "tool"
+        type = "StaticLib"
+        compiler = "gcc"
+        language = "C11"
+        build_systems = ["Makefile"]
+    "#;
+
+    fn workspace() -> Vec<Project> {
+        let mut projects = toml::from_str::<ProjectsFile>(MANIFEST).unwrap().projects;
+        // Dependencies name workspace projects by their full name
+        let app = &mut projects[0];
+        let requirement = app.dependencies.remove("core").unwrap();
+        app.dependencies
+            .insert("libs/core".to_string(), requirement);
+        projects
+    }
+
+    #[test]
+    fn workspace_build_declares_packages_and_subdirectories() {
+        let workspace = workspace();
+        let meson_projects = vec![&workspace[1], &workspace[0]];
+        let build = workspace_build(&workspace, &meson_projects).unwrap();
+        assert!(build.contains("project("), "{}", build);
+        assert!(
+            build.contains("zlib_dep = declare_dependency("),
+            "{}",
+            build
+        );
+        assert!(build.contains("'deps/zlib/include'"), "{}", build);
+        assert!(!build.contains("tool_dep"), "{}", build);
+        let core = build.find("subdir('libs/core')").unwrap();
+        let app = build.find("subdir('app')").unwrap();
+        assert!(core < app, "{}", build);
+    }
+
+    #[test]
+    fn options_offer_every_configuration() {
+        let workspace = workspace();
+        let meson_projects = vec![&workspace[1], &workspace[0]];
+        let options = options(&meson_projects).unwrap();
+        assert!(
+            options.contains(
+                "option('configuration', type: 'combo', choices: ['Debug', 'Release', 'Profile'], value: 'Debug',"
+            ),
+            "{}",
+            options
+        );
+    }
+
+    #[test]
+    fn project_build_links_meson_dependencies() {
+        let workspace = workspace();
+        let meson_projects = vec![&workspace[1], &workspace[0]];
+        let app = &workspace[0];
+        let build =
+            project_build(app, &Compiler::assumed(app), &workspace, &meson_projects).unwrap();
+        assert!(
+            build.contains("app_target = executable('app', app_sources,"),
+            "{}",
+            build
+        );
+        assert!(
+            build.contains("if get_option('configuration') == 'Debug'"),
+            "{}",
+            build
+        );
+        assert!(
+            build.contains("elif get_option('configuration') == 'Release'"),
+            "{}",
+            build
+        );
+        assert!(
+            build.contains("c_args: app_args + ['-DAPP=1'],"),
+            "{}",
+            build
+        );
+        assert!(
+            build.contains("override_options: ['c_std=c11'],"),
+            "{}",
+            build
+        );
+        // tool is built by make, which Meson can't link against
+        assert!(
+            build.contains("dependencies: [libs_core_dep, zlib_dep],"),
+            "{}",
+            build
+        );
+
+        let core = &workspace[1];
+        let build =
+            project_build(core, &Compiler::assumed(core), &workspace, &meson_projects).unwrap();
+        assert!(
+            build.contains("libs_core_target = static_library('core',"),
+            "{}",
+            build
+        );
+        assert!(
+            build.contains("libs_core_dep = declare_dependency(link_with: libs_core_target"),
+            "{}",
+            build
+        );
+    }
+}
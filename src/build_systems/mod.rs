@@ -1,61 +1,292 @@
 // src/build_systems/mod.rs
+pub mod cmake_generator;
 pub mod makefile_generator;
+pub mod meson_generator;
+pub mod native;
+pub mod ninja_generator;
+pub mod plugin;
 pub mod visual_studio_generator;
 
+use crate::build_systems::cmake_generator::CMake;
+use crate::build_systems::makefile_generator::Makefile;
+use crate::build_systems::meson_generator::Meson;
+use crate::build_systems::native::Native;
+use crate::build_systems::ninja_generator::Ninja;
+use crate::build_systems::plugin::Plugin;
+use crate::build_systems::visual_studio_generator::VisualStudio;
+use crate::compilation_database::CompileCommand;
+use crate::compiler::Compiler;
+use crate::config_header::write_config_header;
 use crate::project::Project;
-use crate::build_systems::makefile_generator::generate_makefile;
-use crate::build_systems::visual_studio_generator::generate_visual_studio;
-
-pub fn generate_build_configs(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
-    for build_system in &project.build_systems {
-        match build_system.as_str() {
-            "Makefile" => generate_makefile(project)?,
-            "VisualStudio" => generate_visual_studio(project)?,
-            _ => eprintln!("Unsupported build system: {}", build_system),
+use crate::version_header::write_version_header;
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A backend named in `build_systems` or with `-b`. Generating writes its
+/// files for one project; building and cleaning run it on that project.
+pub trait BuildSystem: Sync {
+    fn name(&self) -> &str;
+
+    /// One line for `cpm --list-build-systems`.
+    fn description(&self) -> &str;
+
+    /// Whether generating needs a working compiler, rather than just the
+    /// one named in the manifest.
+    fn needs_compiler(&self) -> bool {
+        true
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn clean(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// The command the backend compiles `source` with, relative to the
+    /// project directory, for `compile_commands.json`. By default the one the
+    /// Native backend runs, whose flags the generators share.
+    fn compile_command(
+        &self,
+        project: &Project,
+        workspace: &[Project],
+        compiler: &Compiler,
+        configuration: &str,
+        source: &str,
+    ) -> CompileCommand {
+        let object = native::object_path(project, compiler, source);
+        CompileCommand::new(
+            Path::new(&project.name),
+            native::compile_command(project, workspace, compiler, configuration, source, &object),
+            source,
+            &object,
+        )
+    }
+
+    /// Where `request` puts the project's outputs, relative to the workspace.
+    fn output_dir(&self, project: &Project, _request: &BuildRequest) -> String {
+        format!("{}/{}", project.name, project.output_dir())
+    }
+}
+
+fn builtin_build_systems() -> Vec<Box<dyn BuildSystem>> {
+    vec![
+        Box::new(Makefile),
+        Box::new(VisualStudio),
+        Box::new(Native),
+        Box::new(CMake),
+        Box::new(Ninja),
+        Box::new(Meson),
+    ]
+}
+
+/// Every build system cpm knows, built-in ones first, then plugins that
+/// don't shadow them.
+pub fn build_systems() -> Vec<Box<dyn BuildSystem>> {
+    let mut build_systems = builtin_build_systems();
+    for plugin in Plugin::discover() {
+        if !build_systems.iter().any(|b| b.name() == plugin.name()) {
+            build_systems.push(Box::new(plugin));
         }
     }
+    build_systems
+}
+
+/// The built-in build system called `name`, else the plugin providing it.
+pub fn find_build_system(name: &str) -> Result<Box<dyn BuildSystem>, Box<dyn std::error::Error>> {
+    if let Some(build_system) = builtin_build_systems()
+        .into_iter()
+        .find(|b| b.name() == name)
+    {
+        return Ok(build_system);
+    }
+    match Plugin::find(name) {
+        Some(plugin) => Ok(Box::new(plugin)),
+        None => Err(format!(
+            "Unsupported build system: {} (see cpm --list-build-systems)",
+            name
+        )
+        .into()),
+    }
+}
+
+/// What generators need to know beyond the project itself.
+pub struct GenerateContext<'a> {
+    pub workspace: &'a [Project],
+    pub manifest_path: &'a str,
+    pub build_system: Option<&'a str>, // Selected with `-b`; replaces the manifest's `build_systems`
+    pub configuration: Option<&'a str>, // Selected with `-c`; each project's first otherwise
+}
+
+/// What `cpm build` or `cpm clean` was asked for, shared by every project.
+pub struct BuildRequest<'a> {
+    pub workspace: &'a [Project],
+    pub manifest_path: &'a str,
+    pub compiler: &'a Compiler,
+    pub build_system: Option<&'a str>,
+    pub configuration: Option<&'a str>,
+    pub jobs: usize,
+}
+
+impl BuildRequest<'_> {
+    /// The configuration selected with `-c`, or the project's first.
+    pub fn configuration<'a>(&'a self, project: &'a Project) -> &'a str {
+        self.configuration
+            .unwrap_or(project.default_configuration())
+    }
+}
+
+/// Writes the project's generated headers and build files, returning the
+/// compiler they were generated for.
+pub fn generate_build_configs(
+    project: &Project,
+    context: &GenerateContext,
+) -> Result<Compiler, Box<dyn std::error::Error>> {
+    let build_systems = match context.build_system {
+        Some(build_system) => vec![find_build_system(build_system)?],
+        None => project
+            .build_systems
+            .iter()
+            .map(|name| find_build_system(name))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    let compiler = match Compiler::detect(project) {
+        Ok(compiler) => compiler,
+        // MSBuild finds the compiler itself, so Visual Studio files can be generated anywhere
+        Err(e) if build_systems.iter().all(|b| !b.needs_compiler()) => {
+            warn!(
+                "{}; generating for {} as named in the manifest",
+                e, project.compiler
+            );
+            Compiler::assumed(project)
+        }
+        Err(e) => return Err(e),
+    };
+    write_config_header(project, context.workspace, &compiler)?;
+    write_version_header(project)?;
+    for build_system in build_systems {
+        build_system.generate(project, context, &compiler)?;
+    }
+    Ok(compiler)
+}
+
+/// The build system `project` is built with: `selected` with `-b`, else
+/// the first in its manifest.
+pub fn selected_build_system(
+    project: &Project,
+    selected: Option<&str>,
+) -> Result<Box<dyn BuildSystem>, Box<dyn std::error::Error>> {
+    let name = selected
+        .or_else(|| project.build_systems.first().map(String::as_str))
+        .unwrap_or("Makefile");
+    find_build_system(name)
+}
+
+pub fn build_project(
+    project: &Project,
+    request: &BuildRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let build_system = selected_build_system(project, request.build_system)?;
+    build_system.build(project, request)?;
+    info!(
+        "Built {} into {}",
+        project.name,
+        build_system.output_dir(project, request)
+    );
     Ok(())
 }
 
-pub fn build_project(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
-    // Determine which build system to use for building
-    // For simplicity, we'll use the first build system specified
-    if let Some(build_system) = project.build_systems.first() {
-        match build_system.as_str() {
-            "Makefile" => {
-                // Run Makefile
-                let status = std::process::Command::new("make")
-                    .current_dir(&project.name)
-                    .status()?;
-                if !status.success() {
-                    return Err("Build failed".into());
-                }
-            }
-            "VisualStudio" => {
-                // Run msbuild.exe
-                let solution_file = format!("{}.sln", project.name);
-                let status = std::process::Command::new("msbuild.exe")
-                    .arg(solution_file)
-                    .current_dir(&project.name)
-                    .status();
-
-                match status {
-                    Ok(status) => {
-                        if !status.success() {
-                            return Err(format!("Build failed for {} with exit code {:?}", project.name, status.code()).into());
-                        }
-                    }
-                    Err(e) => {
-                        return Err(format!("Failed to execute msbuild.exe: {}", e).into());
-                    }
-                }
-            }
-            _ => {
-                eprintln!("Unsupported build system: {}", build_system);
+pub fn clean_project(
+    project: &Project,
+    request: &BuildRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    selected_build_system(project, request.build_system)?.clean(project, request)
+}
+
+/// Runs a build tool, failing with `what` if it exits unsuccessfully.
+pub fn run_tool(command: &mut Command, what: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} failed with {}", what, status).into());
+    }
+    Ok(())
+}
+
+/// Whether `project` is generated for `build_system` in this run, for
+/// generators that write one file for the whole workspace.
+pub fn uses_build_system(project: &Project, context: &GenerateContext, build_system: &str) -> bool {
+    match context.build_system {
+        Some(selected) => selected == build_system,
+        None => project.build_systems.iter().any(|b| b == build_system),
+    }
+}
+
+/// The way back to the workspace root from the project directory, which is
+/// deeper than one level for nested names such as `libs/net`.
+pub fn to_workspace(project: &Project) -> String {
+    "../".repeat(Path::new(&project.name).components().count())
+}
+
+/// The directories custom command outputs go in, each once, so a recipe
+/// can create them before running the command.
+pub fn output_dirs(outputs: &[String]) -> Vec<String> {
+    let mut dirs = Vec::new();
+    for output in outputs {
+        if let Some(dir) = Path::new(output)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+        {
+            if !dir.is_empty() && !dirs.contains(&dir) {
+                dirs.push(dir);
             }
         }
+    }
+    dirs
+}
+
+/// `arguments` as one POSIX shell command line.
+pub fn shell_join(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|a| shell_quote(a))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `argument` single-quoted for a POSIX shell, unless it needs no quoting.
+pub fn shell_quote(argument: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=+,:@%".contains(c);
+    if !argument.is_empty() && argument.chars().all(plain) {
+        argument.to_string()
     } else {
-        eprintln!("No build systems specified for project {}", project.name);
+        format!("'{}'", argument.replace('\'', "'\\''"))
+    }
+}
+
+/// Writes a generated file unless it already has `content`, so tools that
+/// watch it don't rerun needlessly.
+pub fn write_if_changed(path: &Path, content: &str) -> std::io::Result<()> {
+    if fs::read_to_string(path).ok().as_deref() == Some(content) {
+        return Ok(());
     }
+    fs::write(path, content)?;
+    info!("Wrote {}", path.display());
     Ok(())
 }
@@ -0,0 +1,935 @@
+// src/build_systems/native.rs
+use crate::build_systems::{to_workspace, BuildRequest, BuildSystem, GenerateContext};
+use crate::compiler::Compiler;
+use crate::hooks::{run_hooks, shell};
+use crate::project::{Project, SOURCE_EXTENSIONS};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+// Kept next to the probe cache in `<output_dir>/.cpm`
+const DATABASE_FILE: &str = "build.json";
+
+/// What each output was last built from: a hash of its command line and the
+/// size, modification time and content hash of every file it read.
+#[derive(Serialize, Deserialize, Default)]
+struct BuildDatabase {
+    #[serde(default)]
+    entries: BTreeMap<String, Entry>, // Keyed by output path, relative to the project directory
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    command: String,
+    inputs: Vec<InputRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct InputRecord {
+    path: String,
+    size: u64,
+    modified: u128, // Nanoseconds since the epoch
+    hash: String,
+}
+
+struct CompileJob {
+    source: String,
+    object: String,
+    command: Vec<String>,
+}
+
+/// cpm compiling and linking the project itself, with no generated files.
+pub struct Native;
+
+impl BuildSystem for Native {
+    fn name(&self) -> &str {
+        "Native"
+    }
+
+    fn description(&self) -> &str {
+        "Built by cpm directly from the manifest"
+    }
+
+    // Builds itself from the manifest; there is nothing to write
+    fn generate(
+        &self,
+        _project: &Project,
+        _context: &GenerateContext,
+        _compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        build_native(
+            project,
+            request.workspace,
+            request.compiler,
+            request.configuration(project),
+            request.jobs,
+        )
+    }
+
+    /// Removes objects, the build database and everything `link` writes.
+    fn clean(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let output_dir = Path::new(&project.name).join(project.output_dir());
+        for dir in [output_dir.join("obj"), output_dir.join(".cpm")] {
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+        let mut files = vec![artifact_path(project, request.compiler)];
+        files.extend(link_library(project, request.compiler));
+        if let Some(soname) = soname(project, request.compiler) {
+            files.push(format!("{}/{}", project.output_dir(), soname));
+        }
+        for file in files {
+            let path = Path::new(&project.name).join(file);
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds `project` for `configuration` without a generated build file:
+/// runs its hooks and custom commands, compiles out-of-date sources with up
+/// to `jobs` compilers at a time and links the result. `projects` is the
+/// workspace, whose libraries are linked when named as dependencies.
+pub fn build_native(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(&project.name)?;
+    let project_dir = fs::canonicalize(&project.name)?;
+    let work_dir = project_dir.join(project.output_dir()).join(".cpm");
+    fs::create_dir_all(&work_dir)?;
+
+    run_hooks(
+        project,
+        &project.pre_build_commands(configuration),
+        &project_dir,
+        configuration,
+    )?;
+    run_custom_commands(project, &project_dir)?;
+
+    let database_path = work_dir.join(DATABASE_FILE);
+    let mut database = BuildDatabase::load(&database_path);
+    let mut stale = Vec::new();
+    let mut objects = Vec::new();
+    for source in sources(project, &project_dir) {
+        let object = object_path(project, compiler, &source);
+        let command = compile_command(project, projects, compiler, configuration, &source, &object);
+        if !database.is_current(&object, &command, &project_dir) {
+            stale.push(CompileJob {
+                source,
+                object: object.clone(),
+                command,
+            });
+        }
+        objects.push(object);
+    }
+    if objects.is_empty() {
+        return Err(format!(
+            "No sources found in {}",
+            project_dir.join(&project.structure.src_dir).display()
+        )
+        .into());
+    }
+
+    let compiled = Mutex::new(Vec::new());
+    let errors = Mutex::new(Vec::new());
+    let queue = Mutex::new(stale.iter().rev().collect::<Vec<_>>());
+    let workers = jobs.max(1).min(stale.len());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                // Like make, stop starting new jobs once one has failed
+                if !errors.lock().unwrap().is_empty() {
+                    break;
+                }
+                let job = match queue.lock().unwrap().pop() {
+                    Some(job) => job,
+                    None => break,
+                };
+                match compile(job, compiler, &project_dir) {
+                    Ok(entry) => compiled.lock().unwrap().push((job.object.clone(), entry)),
+                    Err(e) => errors.lock().unwrap().push(e),
+                }
+            });
+        }
+    });
+    database.entries.extend(compiled.into_inner().unwrap());
+    database.save(&database_path)?;
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        return Err(errors.join("\n").into());
+    }
+
+    link(
+        project,
+        projects,
+        compiler,
+        configuration,
+        &objects,
+        &project_dir,
+        &mut database,
+    )?;
+    database.save(&database_path)?;
+
+    run_hooks(
+        project,
+        &project.post_build_commands(configuration),
+        &project_dir,
+        configuration,
+    )
+}
+
+/// The file the project builds, relative to its directory. Names follow the
+/// target platform: `libx.a`/`x.lib`, `libx.so`/`libx.dylib`/`x.dll` and
+/// `x`/`x.exe`; a versioned shared library on ELF is `libx.so.1.2.3`.
+pub fn artifact_path(project: &Project, compiler: &Compiler) -> String {
+    let stem = project.output_stem();
+    let file = match project.project_type.as_str() {
+        "StaticLib" if compiler.is_msvc() => format!("{}.lib", stem),
+        "StaticLib" => format!("lib{}.a", stem),
+        "SharedLib" if targets_windows(compiler) => format!("{}.dll", stem),
+        "SharedLib" if compiler.target.contains("apple") => format!("lib{}.dylib", stem),
+        "SharedLib" => match project.semantic_version() {
+            Some(v) => format!("lib{}.so.{}.{}.{}", stem, v.major, v.minor, v.patch),
+            None => format!("lib{}.so", stem),
+        },
+        _ if targets_windows(compiler) => format!("{}.exe", stem),
+        _ => stem.to_string(),
+    };
+    format!("{}/{}", project.output_dir(), file)
+}
+
+/// What dependents link against, relative to the project directory: the
+/// import library of an MSVC DLL and the unversioned name of an ELF library.
+pub fn link_library(project: &Project, compiler: &Compiler) -> Option<String> {
+    let stem = project.output_stem();
+    match project.project_type.as_str() {
+        "StaticLib" => Some(artifact_path(project, compiler)),
+        "SharedLib" if compiler.is_msvc() => Some(format!("{}/{}.lib", project.output_dir(), stem)),
+        "SharedLib" if project.semantic_version().is_some() && !targets_windows(compiler) => {
+            Some(format!("{}/lib{}.so", project.output_dir(), stem))
+        }
+        "SharedLib" => Some(artifact_path(project, compiler)),
+        _ => None,
+    }
+}
+
+fn targets_windows(compiler: &Compiler) -> bool {
+    compiler.is_msvc() || compiler.target.contains("windows") || compiler.target.contains("mingw")
+}
+
+/// Sources in `src_dir` followed by generated ones, relative to the project directory.
+pub fn sources(project: &Project, project_dir: &Path) -> Vec<String> {
+    let mut sources = Vec::new();
+    for extension in SOURCE_EXTENSIONS {
+        let pattern = project_dir
+            .join(&project.structure.src_dir)
+            .join(format!("*.{}", extension));
+        for path in glob::glob(&pattern.to_string_lossy())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            if let Ok(relative) = path.strip_prefix(project_dir) {
+                sources.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    sources.sort();
+    for generated in project.generated_sources() {
+        if !sources.iter().any(|s| s == generated) {
+            sources.push(generated.to_string());
+        }
+    }
+    sources
+}
+
+pub fn object_path(project: &Project, compiler: &Compiler, source: &str) -> String {
+    let stem = Path::new(source).with_extension("");
+    // Sources outside the project directory still get an object inside it
+    let stem = stem
+        .to_string_lossy()
+        .replace('\\', "/")
+        .replace("../", "__/");
+    let extension = if compiler.is_msvc() { "obj" } else { "o" };
+    format!("{}/obj/{}.{}", project.output_dir(), stem, extension)
+}
+
+/// Include directories: the project's own, generated ones, then those of
+/// workspace dependencies and installed packages.
+pub fn include_dirs(project: &Project, projects: &[Project]) -> Vec<String> {
+    let mut dirs = vec![project.structure.include_dir.clone()];
+    dirs.extend(project.additional_include_dirs.iter().cloned());
+    dirs.extend(project.generated_include_dirs());
+    let workspace = to_workspace(project);
+    for dependency in workspace_dependencies(project, projects) {
+        dirs.push(format!(
+            "{}{}/{}",
+            workspace, dependency.name, dependency.structure.include_dir
+        ));
+    }
+    for package in packages(project, projects) {
+        dirs.push(format!(
+            "{}{}/{}/include",
+            workspace,
+            project.deps_dir(),
+            package
+        ));
+    }
+    dirs
+}
+
+pub fn compile_command(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+    source: &str,
+    object: &str,
+) -> Vec<String> {
+    let mut command = vec![compiler.command.clone()];
+    command.extend(compiler.compile_flags(project, configuration));
+    let (include, define) = if compiler.is_msvc() {
+        ("/I", "/D")
+    } else {
+        ("-I", "-D")
+    };
+    command.extend(
+        include_dirs(project, projects)
+            .iter()
+            .map(|dir| format!("{}{}", include, dir)),
+    );
+    command.extend(
+        project
+            .preprocessor_definitions
+            .iter()
+            .map(|d| format!("{}{}", define, d)),
+    );
+    if compiler.is_msvc() {
+        command.extend([
+            "/c".to_string(),
+            source.to_string(),
+            format!("/Fo{}", object),
+            "/sourceDependencies".to_string(),
+            format!("{}.json", object),
+        ]);
+    } else {
+        command.extend([
+            "-MD".to_string(),
+            "-MF".to_string(),
+            format!("{}.d", object),
+            "-c".to_string(),
+            "-o".to_string(),
+            object.to_string(),
+            source.to_string(),
+        ]);
+    }
+    command
+}
+
+/// Compiles one source and records the headers it included.
+fn compile(job: &CompileJob, compiler: &Compiler, project_dir: &Path) -> Result<Entry, String> {
+    println!("   Compiling {}", job.source);
+    let object = project_dir.join(&job.object);
+    if let Some(parent) = object.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    let output = std::process::Command::new(&job.command[0])
+        .args(&job.command[1..])
+        .current_dir(project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", job.command[0], e))?;
+    // Printed whole so parallel jobs don't interleave their diagnostics
+    let mut diagnostics = String::from_utf8_lossy(&output.stdout).to_string();
+    diagnostics.push_str(&String::from_utf8_lossy(&output.stderr));
+    if compiler.is_msvc() {
+        // cl echoes the name of every file it compiles
+        diagnostics = diagnostics
+            .lines()
+            .skip(1)
+            .map(|l| format!("{}\n", l))
+            .collect();
+    }
+    if !diagnostics.trim().is_empty() {
+        eprint!("{}", diagnostics);
+    }
+    if !output.status.success() {
+        return Err(format!("Failed to compile {}", job.source));
+    }
+
+    let dependencies = if compiler.is_msvc() {
+        fs::read_to_string(object.with_extension("obj.json"))
+            .map(|json| parse_source_dependencies(&json))
+            .unwrap_or_default()
+    } else {
+        fs::read_to_string(project_dir.join(format!("{}.d", job.object)))
+            .map(|depfile| parse_depfile(&depfile))
+            .unwrap_or_default()
+    };
+    let mut inputs = vec![job.source.clone()];
+    for dependency in dependencies {
+        if !inputs.contains(&dependency) {
+            inputs.push(dependency);
+        }
+    }
+    Ok(Entry {
+        command: command_hash(&job.command),
+        inputs: inputs
+            .iter()
+            .filter_map(|input| InputRecord::read(input, project_dir))
+            .collect(),
+    })
+}
+
+/// The prerequisites of the single rule in a depfile written by `-MD`.
+fn parse_depfile(content: &str) -> Vec<String> {
+    let joined = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let prerequisites = match joined.find(": ") {
+        Some(index) => &joined[index + 2..],
+        None => return Vec::new(),
+    };
+    let mut paths = Vec::new();
+    let mut current = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => current.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'$') => current.push(chars.next().unwrap()),
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    paths.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        paths.push(current);
+    }
+    paths
+}
+
+/// The headers listed by MSVC's `/sourceDependencies`.
+fn parse_source_dependencies(json: &str) -> Vec<String> {
+    let value: serde_json::Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(_) => return Vec::new(),
+    };
+    value["Data"]["Includes"]
+        .as_array()
+        .map(|includes| {
+            includes
+                .iter()
+                .filter_map(|i| i.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reruns custom commands whose outputs are missing or older than an input.
+fn run_custom_commands(
+    project: &Project,
+    project_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let modified = |path: &String| {
+        fs::metadata(project_dir.join(path))
+            .and_then(|m| m.modified())
+            .ok()
+    };
+    for custom in &project.custom_commands {
+        let oldest_output = custom
+            .outputs
+            .iter()
+            .map(modified)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|t| t.into_iter().min());
+        let newest_input = custom.inputs.iter().filter_map(modified).max();
+        let up_to_date = match (oldest_output, newest_input) {
+            (Some(output), Some(input)) => output >= input,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if up_to_date {
+            continue;
+        }
+        println!("{}", custom.message());
+        for output in &custom.outputs {
+            if let Some(parent) = project_dir.join(output).parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let command = custom.expanded_command();
+        let status = shell(&command).current_dir(project_dir).status()?;
+        if !status.success() {
+            return Err(format!("`{}` failed with {}", command, status).into());
+        }
+    }
+    Ok(())
+}
+
+/// Workspace projects this one depends on, directly or not, ordered so each
+/// comes before the libraries it needs, as static linking requires.
+pub fn workspace_dependencies<'a>(project: &Project, projects: &'a [Project]) -> Vec<&'a Project> {
+    fn visit<'a>(project: &Project, projects: &'a [Project], order: &mut Vec<&'a Project>) {
+        let mut names = project.dependencies.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            if let Some(dependency) = projects.iter().find(|p| &p.name == name) {
+                if !order.iter().any(|p| p.name == dependency.name)
+                    && dependency.name != project.name
+                {
+                    visit(dependency, projects, order);
+                    order.push(dependency);
+                }
+            }
+        }
+    }
+    let mut order = Vec::new();
+    visit(project, projects, &mut order);
+    order.reverse();
+    order
+}
+
+/// Installed packages used by the project or the workspace libraries it links.
+fn packages(project: &Project, projects: &[Project]) -> Vec<String> {
+    let mut packages = Vec::new();
+    for owner in std::iter::once(project).chain(workspace_dependencies(project, projects)) {
+        let mut names = owner
+            .dependencies
+            .keys()
+            .filter(|name| !projects.iter().any(|p| &p.name == *name))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            if !packages.contains(&name) {
+                packages.push(name);
+            }
+        }
+    }
+    packages
+}
+
+fn link(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+    objects: &[String],
+    project_dir: &Path,
+    database: &mut BuildDatabase,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let artifact = artifact_path(project, compiler);
+    let libraries = workspace_libraries(project, projects, compiler);
+    let command = link_command(
+        project,
+        projects,
+        compiler,
+        configuration,
+        objects,
+        &libraries,
+        project_dir,
+    );
+    let inputs = objects
+        .iter()
+        .chain(&libraries)
+        .cloned()
+        .collect::<Vec<_>>();
+    if database.is_current(&artifact, &command, project_dir) {
+        println!("       Fresh {}", project.name);
+        return Ok(());
+    }
+
+    println!("     Linking {}", artifact);
+    let output = project_dir.join(&artifact);
+    // `ar` adds to an existing archive, which would keep objects of deleted sources
+    if project.project_type == "StaticLib" && output.exists() {
+        fs::remove_file(&output)?;
+    }
+    let status = std::process::Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(project_dir)
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", command[0], e))?;
+    if !status.success() {
+        return Err(format!("Failed to link {}", artifact).into());
+    }
+    create_library_links(project, compiler, project_dir)?;
+
+    database.entries.insert(
+        artifact,
+        Entry {
+            command: command_hash(&command),
+            inputs: inputs
+                .iter()
+                .filter_map(|input| InputRecord::read(input, project_dir))
+                .collect(),
+        },
+    );
+    Ok(())
+}
+
+/// The workspace libraries `project` links, relative to its directory.
+fn workspace_libraries(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+) -> Vec<String> {
+    workspace_dependencies(project, projects)
+        .iter()
+        .filter_map(|d| {
+            link_library(d, compiler)
+                .map(|library| format!("{}{}/{}", to_workspace(project), d.name, library))
+        })
+        .collect()
+}
+
+pub fn link_command(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+    objects: &[String],
+    libraries: &[String],
+    project_dir: &Path,
+) -> Vec<String> {
+    let artifact = artifact_path(project, compiler);
+    let packages = packages(project, projects);
+    let deps_dir = format!("{}{}", to_workspace(project), project.deps_dir());
+    let mut command = Vec::new();
+    if project.project_type == "StaticLib" {
+        command.push(compiler.archiver.clone());
+        if compiler.is_msvc() {
+            command.push("/nologo".to_string());
+            command.push(format!("/OUT:{}", artifact));
+        } else {
+            command.push("rcs".to_string());
+            command.push(artifact);
+        }
+        command.extend(objects.iter().cloned());
+        return command;
+    }
+
+    command.push(compiler.command.clone());
+    if compiler.is_msvc() {
+        command.push("/nologo".to_string());
+        if project.project_type == "SharedLib" {
+            command.push("/LD".to_string());
+        }
+        command.extend(objects.iter().cloned());
+        command.extend(libraries.iter().cloned());
+        command.extend(packages.iter().map(|p| format!("{}.lib", p)));
+        command.push(format!("/Fe{}", artifact));
+        command.push("/link".to_string());
+        command.extend(compiler.link_flags(project, configuration));
+        command.extend(
+            packages
+                .iter()
+                .map(|p| format!("/LIBPATH:{}/{}/lib", deps_dir, p)),
+        );
+        return command;
+    }
+
+    command.extend(compiler.link_flags(project, configuration));
+    if project.project_type == "SharedLib" {
+        if compiler.target.contains("apple") {
+            command.push("-dynamiclib".to_string());
+        } else {
+            command.push("-shared".to_string());
+            // Dependents would otherwise record the relative path they linked
+            // it by, which only resolves from their own directory
+            if !targets_windows(compiler) {
+                let soname = soname(project, compiler).unwrap_or_else(|| file_name(&artifact));
+                command.push(format!("-Wl,-soname,{}", soname));
+            }
+        }
+    }
+    command.push("-o".to_string());
+    command.push(artifact);
+    command.extend(objects.iter().cloned());
+    command.extend(libraries.iter().cloned());
+    command.extend(packages.iter().map(|p| format!("-L{}/{}/lib", deps_dir, p)));
+    command.extend(packages.iter().map(|p| format!("-l{}", p)));
+    // Let executables find workspace shared libraries where they were built
+    if !targets_windows(compiler) {
+        let depth = Path::new(&project.name).components().count();
+        let workspace_dir = project_dir.ancestors().nth(depth).unwrap_or(project_dir);
+        for dependency in workspace_dependencies(project, projects) {
+            if dependency.project_type == "SharedLib" {
+                let dir = workspace_dir
+                    .join(&dependency.name)
+                    .join(dependency.output_dir());
+                command.push(format!("-Wl,-rpath,{}", dir.display()));
+            }
+        }
+    }
+    command
+}
+
+/// `libx.so.1` for a versioned shared library on ELF platforms.
+pub fn soname(project: &Project, compiler: &Compiler) -> Option<String> {
+    let version = project.semantic_version()?;
+    if project.project_type != "SharedLib"
+        || targets_windows(compiler)
+        || compiler.target.contains("apple")
+    {
+        return None;
+    }
+    let stem = project.output_stem();
+    Some(format!("lib{}.so.{}", stem, version.major))
+}
+
+/// The soname and link-time symlinks next to a versioned shared library.
+fn create_library_links(
+    project: &Project,
+    compiler: &Compiler,
+    project_dir: &Path,
+) -> std::io::Result<()> {
+    let soname = match soname(project, compiler) {
+        Some(soname) => soname,
+        None => return Ok(()),
+    };
+    let artifact = artifact_path(project, compiler);
+    let file_name = file_name(&artifact);
+    let output_dir = project_dir.join(project.output_dir());
+    let library = soname
+        .rsplit_once('.')
+        .map(|(library, _)| library.to_string())
+        .unwrap_or_default();
+    for (link, target) in [
+        (soname.as_str(), file_name.as_str()),
+        (library.as_str(), soname.as_str()),
+    ] {
+        let link = output_dir.join(link);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link)?;
+        }
+        symlink(target, &link)?;
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    let source = link.with_file_name(target);
+    fs::copy(source, link).map(|_| ())
+}
+
+fn command_hash(command: &[String]) -> String {
+    hex(&Sha256::digest(command.join("\0").as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl BuildDatabase {
+    /// A missing or unreadable database just means everything gets rebuilt.
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Whether `output` exists and was built by `command` from inputs that
+    /// haven't changed since. Inputs that were only touched are re-recorded
+    /// so their content isn't hashed again next time.
+    fn is_current(&mut self, output: &str, command: &[String], project_dir: &Path) -> bool {
+        if !project_dir.join(output).exists() {
+            return false;
+        }
+        let entry = match self.entries.get_mut(output) {
+            Some(entry) if entry.command == command_hash(command) => entry,
+            _ => return false,
+        };
+        for input in entry.inputs.iter_mut() {
+            match input.refresh(project_dir) {
+                Some(current) => *input = current,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl InputRecord {
+    fn read(path: &str, project_dir: &Path) -> Option<Self> {
+        let full_path = project_dir.join(path);
+        let metadata = fs::metadata(&full_path).ok()?;
+        let content = fs::read(&full_path).ok()?;
+        Some(InputRecord {
+            path: path.to_string(),
+            size: metadata.len(),
+            modified: modified_nanos(&metadata),
+            hash: hex(&Sha256::digest(&content)),
+        })
+    }
+
+    /// The record for the file as it is now, or None if its content changed.
+    fn refresh(&self, project_dir: &Path) -> Option<Self> {
+        let metadata = fs::metadata(project_dir.join(&self.path)).ok()?;
+        if metadata.len() == self.size && modified_nanos(&metadata) == self.modified {
+            return Some(self.clone());
+        }
+        let current = InputRecord::read(&self.path, project_dir)?;
+        (current.hash == self.hash).then_some(current)
+    }
+}
+
+fn modified_nanos(metadata: &fs::Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectsFile;
+
+    const MANIFEST: &str = r#"
+        [[projects]]
+        name = "core"
+        type = "StaticLib"
+        compiler = "gcc"
+        language = "C11"
+        dependencies = { zlib = "1.3" }
+
+        [[projects]]
+        name = "libs/net"
+        type = "SharedLib"
+        compiler = "gcc"
+        language = "C11"
+        dependencies = { core = "*" }
+    "#;
+
+    #[test]
+    fn nested_projects_reach_the_workspace_root() {
+        let workspace = toml::from_str::<ProjectsFile>(MANIFEST).unwrap().projects;
+        let net = &workspace[1];
+        assert_eq!(
+            include_dirs(net, &workspace),
+            ["include", "../../core/include", "../../deps/zlib/include"]
+        );
+
+        let compiler = Compiler::assumed(net);
+        let libraries = workspace_libraries(net, &workspace, &compiler);
+        assert_eq!(libraries, ["../../core/build/libcore.a"]);
+        assert_eq!(artifact_path(net, &compiler), "build/libnet.so");
+        let command = link_command(
+            net,
+            &workspace,
+            &compiler,
+            "Debug",
+            &[],
+            &libraries,
+            Path::new("/work/libs/net"),
+        );
+        assert!(
+            command.contains(&"-L../../deps/zlib/lib".to_string()),
+            "{:?}",
+            command
+        );
+
+        let core = &workspace[0];
+        assert_eq!(
+            include_dirs(core, &workspace),
+            ["include", "../deps/zlib/include"]
+        );
+    }
+
+    #[test]
+    fn depfiles_join_continuations_and_unescape_paths() {
+        let depfile = "build/obj/src/main.o: src/main.c \\\n include/my\\ header.h \\\r\n /usr/include/stdio.h\n";
+        assert_eq!(
+            parse_depfile(depfile),
+            ["src/main.c", "include/my header.h", "/usr/include/stdio.h"]
+        );
+        assert_eq!(parse_depfile("a.o: price$$.h\n"), ["price$.h"]);
+        assert!(parse_depfile("").is_empty());
+        assert_eq!(
+            parse_source_dependencies(r#"{"Data": {"Includes": ["c:\\inc\\a.h"]}}"#),
+            ["c:\\inc\\a.h"]
+        );
+    }
+
+    #[test]
+    fn build_database_notices_changed_inputs_and_commands() {
+        let dir = std::env::temp_dir().join(format!("cpm-build-db-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+        fs::write(dir.join("main.o"), "").unwrap();
+        let command = vec!["cc".to_string(), "-c".to_string(), "main.c".to_string()];
+        let mut database = BuildDatabase::default();
+        assert!(!database.is_current("main.o", &command, &dir));
+
+        database.entries.insert(
+            "main.o".to_string(),
+            Entry {
+                command: command_hash(&command),
+                inputs: vec![InputRecord::read("main.c", &dir).unwrap()],
+            },
+        );
+        assert!(database.is_current("main.o", &command, &dir));
+        let other = vec!["cc".to_string(), "-O2".to_string(), "main.c".to_string()];
+        assert!(!database.is_current("main.o", &other, &dir));
+
+        // Rewriting the same content only refreshes the record
+        let recorded = database.entries["main.o"].inputs[0].clone();
+        fs::write(dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+        assert!(database.is_current("main.o", &command, &dir));
+        assert_eq!(database.entries["main.o"].inputs[0].hash, recorded.hash);
+
+        fs::write(dir.join("main.c"), "int main(void) { return 1; }\n").unwrap();
+        assert!(!database.is_current("main.o", &command, &dir));
+
+        fs::remove_file(dir.join("main.o")).unwrap();
+        assert!(!database.is_current("main.o", &command, &dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
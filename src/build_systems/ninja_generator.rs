@@ -0,0 +1,498 @@
+// src/build_systems/ninja_generator.rs
+use crate::build_systems::native::{
+    artifact_path, include_dirs, link_command, link_library, object_path, soname, sources,
+    workspace_dependencies,
+};
+use crate::build_systems::{
+    output_dirs, run_tool, shell_join, shell_quote, to_workspace, uses_build_system,
+    write_if_changed, BuildRequest, BuildSystem, GenerateContext,
+};
+use crate::compilation_database::CompileCommand;
+use crate::compiler::Compiler;
+use crate::dependency_resolver::resolve_dependencies;
+use crate::hooks::hook_environment;
+use crate::path_lookup::cpm_program;
+use crate::project::Project;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// A workspace `build.ninja` that includes a fragment per project.
+pub struct Ninja;
+
+impl BuildSystem for Ninja {
+    fn name(&self) -> &str {
+        "Ninja"
+    }
+
+    fn description(&self) -> &str {
+        "build.ninja for the workspace with a fragment per project"
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        generate_ninja(project, context, compiler)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        run_tool(
+            Command::new("ninja")
+                .args(["-f", &ninja_file_name(project), &project.name])
+                .args(["-j", &request.jobs.to_string()]),
+            &format!("Build of {}", project.name),
+        )
+    }
+
+    fn clean(
+        &self,
+        project: &Project,
+        _request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !Path::new(&ninja_file_name(project)).exists() {
+            return Ok(());
+        }
+        // Also removes what the project's phony target was built from
+        run_tool(
+            Command::new("ninja").args([
+                "-f",
+                &ninja_file_name(project),
+                "-t",
+                "clean",
+                &project.name,
+            ]),
+            &format!("Clean of {}", project.name),
+        )
+    }
+
+    /// The compile rule, which runs in the workspace root.
+    fn compile_command(
+        &self,
+        project: &Project,
+        workspace: &[Project],
+        compiler: &Compiler,
+        configuration: &str,
+        source: &str,
+    ) -> CompileCommand {
+        let object = workspace_path(&project.name, &object_path(project, compiler, source));
+        let source = workspace_path(&project.name, source);
+        let mut command = vec![compiler.command.clone()];
+        command.extend(cflags(project, workspace, compiler, configuration));
+        command.extend([
+            "-MD".to_string(),
+            "-MF".to_string(),
+            format!("{}.d", object),
+            "-c".to_string(),
+            source.clone(),
+            "-o".to_string(),
+            object.clone(),
+        ]);
+        CompileCommand::new(Path::new(""), command, &source, &object)
+    }
+}
+
+/// The compiler flags, include directories and definitions of `project`,
+/// with paths relative to the workspace root where the compile rule runs.
+fn cflags(
+    project: &Project,
+    workspace: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+) -> Vec<String> {
+    let mut cflags = compiler.compile_flags(project, configuration);
+    cflags.extend(
+        include_dirs(project, workspace)
+            .iter()
+            .map(|dir| format!("-I{}", workspace_path(&project.name, dir))),
+    );
+    cflags.extend(
+        project
+            .preprocessor_definitions
+            .iter()
+            .map(|d| format!("-D{}", d)),
+    );
+    cflags
+}
+
+/// `build.ninja` for the host and `build-<triple>.ninja` per cross target,
+/// both at the workspace root.
+pub fn ninja_file_name(project: &Project) -> String {
+    match &project.target {
+        Some(target) => format!("build-{}.ninja", target.triple),
+        None => "build.ninja".to_string(),
+    }
+}
+
+/// Writes the project's rules to `<project>/build.ninja` and the workspace
+/// `build.ninja` that shares the rules and includes every Ninja project.
+/// Everything runs from the workspace root, so depfile paths resolve there.
+pub fn generate_ninja(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if compiler.is_msvc() {
+        return Err(format!(
+            "{}: the Ninja backend needs a gcc-compatible compiler",
+            project.name
+        )
+        .into());
+    }
+    let ninja_projects = resolve_dependencies(context.workspace)
+        .into_iter()
+        .filter(|p| uses_build_system(p, context, "Ninja"))
+        .collect::<Vec<_>>();
+    fs::create_dir_all(&project.name)?;
+    let fragment = Path::new(&project.name).join(ninja_file_name(project));
+    write_if_changed(
+        &fragment,
+        &project_rules(project, context, compiler, &ninja_projects)?,
+    )?;
+    write_if_changed(
+        Path::new(&ninja_file_name(project)),
+        &workspace_rules(project, context, &ninja_projects)?,
+    )?;
+    Ok(())
+}
+
+fn workspace_rules(
+    project: &Project,
+    context: &GenerateContext,
+    ninja_projects: &[&Project],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut regenerate = vec![
+        cpm_program(),
+        "generate".to_string(),
+        "-f".to_string(),
+        context.manifest_path.to_string(),
+    ];
+    regenerate.extend(["-b".to_string(), "Ninja".to_string()]);
+    if let Some(configuration) = context.configuration {
+        regenerate.extend(["-c".to_string(), configuration.to_string()]);
+    }
+    if let Some(target) = &project.target {
+        regenerate.extend(["--target".to_string(), target.triple.clone()]);
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by cpm from {}. Do not edit.",
+        context.manifest_path
+    )?;
+    writeln!(out, "ninja_required_version = 1.5\n")?;
+    writeln!(out, "rule compile")?;
+    writeln!(out, "  command = $cc $cflags -MD -MF $out.d -c $in -o $out")?;
+    writeln!(out, "  depfile = $out.d")?;
+    writeln!(out, "  deps = gcc")?;
+    writeln!(out, "  description = Compiling $in\n")?;
+    // Linking, custom commands and hooks run in the project directory like
+    // in the other backends; none of them write depfiles
+    for (rule, description) in [
+        ("link", "Linking $out"),
+        ("custom", "$message"),
+        ("hook", "$message"),
+    ] {
+        writeln!(out, "rule {}", rule)?;
+        writeln!(out, "  command = cd $dir && $cmd")?;
+        writeln!(out, "  description = {}", description)?;
+        if rule == "hook" {
+            writeln!(out, "  pool = console")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "rule regenerate")?;
+    writeln!(
+        out,
+        "  command = {}",
+        ninja_escape(&shell_join(&regenerate))
+    )?;
+    writeln!(out, "  description = Regenerating $out")?;
+    writeln!(out, "  generator = 1\n")?;
+
+    let mut generated = vec![ninja_file_name(project)];
+    generated.extend(
+        ninja_projects
+            .iter()
+            .map(|p| format!("{}/{}", p.name, ninja_file_name(project))),
+    );
+    writeln!(
+        out,
+        "build {}: regenerate {}\n",
+        paths(&generated),
+        path_escape(context.manifest_path)
+    )?;
+    for ninja_project in ninja_projects {
+        writeln!(
+            out,
+            "subninja {}/{}",
+            path_escape(&ninja_project.name),
+            ninja_file_name(project)
+        )?;
+    }
+    Ok(out)
+}
+
+fn project_rules(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+    ninja_projects: &[&Project],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let name = &project.name;
+    let configuration = context
+        .configuration
+        .unwrap_or(project.default_configuration());
+    let project_dir = std::env::current_dir()?.join(name);
+    let in_workspace = |path: &str| workspace_path(name, path);
+
+    let cflags = cflags(project, context.workspace, compiler, configuration);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# {} ({}). Generated by cpm; do not edit.",
+        name, configuration
+    )?;
+    writeln!(out, "cc = {}", ninja_escape(&compiler.command))?;
+    writeln!(out, "cflags = {}", ninja_escape(&shell_join(&cflags)))?;
+    writeln!(out, "dir = {}\n", ninja_escape(name))?;
+
+    for custom in &project.custom_commands {
+        if custom.outputs.is_empty() {
+            continue;
+        }
+        let outputs = custom
+            .outputs
+            .iter()
+            .map(|o| in_workspace(o))
+            .collect::<Vec<_>>();
+        let inputs = custom
+            .inputs
+            .iter()
+            .map(|i| in_workspace(i))
+            .collect::<Vec<_>>();
+        let mut command = output_dirs(&custom.outputs)
+            .iter()
+            .map(|dir| format!("mkdir -p {}", shell_quote(dir)))
+            .collect::<Vec<_>>();
+        command.push(custom.expanded_command());
+        writeln!(out, "build {}: custom {}", paths(&outputs), paths(&inputs))?;
+        writeln!(out, "  cmd = {}", ninja_escape(&command.join(" && ")))?;
+        writeln!(out, "  message = {}\n", ninja_escape(&custom.message()))?;
+    }
+
+    // Objects wait for generated headers and the pre-build hooks on the first
+    // build; after that their depfiles know what they include
+    let mut order_only = project
+        .generated_headers()
+        .iter()
+        .map(|h| in_workspace(h))
+        .collect::<Vec<_>>();
+    if write_hook(
+        &mut out,
+        project,
+        &project_dir,
+        configuration,
+        "prebuild",
+        &project.pre_build_commands(configuration),
+        &[],
+    )? {
+        order_only.push(format!("{}_prebuild", name));
+    }
+    let mut objects = Vec::new();
+    for source in sources(project, Path::new(name)) {
+        let object = object_path(project, compiler, &source);
+        write!(
+            out,
+            "build {}: compile {}",
+            path_escape(&in_workspace(&object)),
+            path_escape(&in_workspace(&source))
+        )?;
+        if !order_only.is_empty() {
+            write!(out, " || {}", paths(&order_only))?;
+        }
+        writeln!(out)?;
+        objects.push(object);
+    }
+    writeln!(out)?;
+
+    let dependencies = workspace_dependencies(project, context.workspace);
+    let libraries = dependencies
+        .iter()
+        .filter_map(|d| {
+            link_library(d, compiler).map(|library| {
+                (
+                    d,
+                    format!("{}{}/{}", to_workspace(project), d.name, library),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    // Libraries of projects built by this file are edges; others must already exist
+    let library_inputs = libraries
+        .iter()
+        .filter(|(d, _)| ninja_projects.iter().any(|p| p.name == d.name))
+        .map(|(_, library)| in_workspace(library));
+    let library_paths = libraries
+        .iter()
+        .map(|(_, library)| library.clone())
+        .collect::<Vec<_>>();
+    let artifact = artifact_path(project, compiler);
+    let mut command = Vec::new();
+    if project.project_type == "StaticLib" {
+        // `ar` adds to an existing archive, which would keep objects of deleted sources
+        command.push(format!("rm -f {}", shell_quote(&artifact)));
+    }
+    command.push(shell_join(&link_command(
+        project,
+        context.workspace,
+        compiler,
+        configuration,
+        &objects,
+        &library_paths,
+        &project_dir,
+    )));
+    let mut links = Vec::new();
+    if let Some(soname) = soname(project, compiler) {
+        let output_dir = project.output_dir();
+        let file_name = Path::new(&artifact)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let library = soname
+            .rsplit_once('.')
+            .map(|(library, _)| library.to_string())
+            .unwrap_or_default();
+        command.push(format!(
+            "ln -sf {} {}/{}",
+            shell_quote(&file_name),
+            output_dir,
+            shell_quote(&soname)
+        ));
+        command.push(format!(
+            "ln -sf {} {}/{}",
+            shell_quote(&soname),
+            output_dir,
+            shell_quote(&library)
+        ));
+        links.push(in_workspace(&format!("{}/{}", output_dir, soname)));
+        links.push(in_workspace(&format!("{}/{}", output_dir, library)));
+    }
+    let mut inputs = objects.iter().map(|o| in_workspace(o)).collect::<Vec<_>>();
+    inputs.extend(library_inputs);
+    write!(out, "build {}", path_escape(&in_workspace(&artifact)))?;
+    if !links.is_empty() {
+        write!(out, " | {}", paths(&links))?;
+    }
+    writeln!(out, ": link {}", paths(&inputs))?;
+    writeln!(out, "  cmd = {}\n", ninja_escape(&command.join(" && ")))?;
+
+    let mut outputs = vec![in_workspace(&artifact)];
+    if write_hook(
+        &mut out,
+        project,
+        &project_dir,
+        configuration,
+        "postbuild",
+        &project.post_build_commands(configuration),
+        &outputs,
+    )? {
+        outputs.push(format!("{}_postbuild", name));
+    }
+    writeln!(
+        out,
+        "build {}: phony {}",
+        path_escape(name),
+        paths(&outputs)
+    )?;
+    writeln!(out, "default {}", path_escape(name))?;
+    Ok(out)
+}
+
+/// An edge running `commands` every time, since its output is never
+/// created. Returns whether there was anything to run.
+fn write_hook(
+    out: &mut String,
+    project: &Project,
+    project_dir: &Path,
+    configuration: &str,
+    stage: &str,
+    commands: &[&str],
+    after: &[String],
+) -> Result<bool, std::fmt::Error> {
+    if commands.is_empty() {
+        return Ok(false);
+    }
+    let environment = hook_environment(project, &project_dir.to_string_lossy(), configuration, '/')
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, shell_quote(&value)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let command = commands
+        .iter()
+        .map(|command| format!("{} sh -c {}", environment, shell_quote(command)))
+        .collect::<Vec<_>>();
+    write!(out, "build {}_{}: hook", project.name, stage)?;
+    if !after.is_empty() {
+        write!(out, " | {}", paths(after))?;
+    }
+    writeln!(out)?;
+    writeln!(out, "  cmd = {}", ninja_escape(&command.join(" && ")))?;
+    writeln!(
+        out,
+        "  message = {} {}\n",
+        stage,
+        ninja_escape(&project.name)
+    )?;
+    Ok(true)
+}
+
+/// `path`, relative to the project directory, as seen from the workspace root.
+fn workspace_path(project_name: &str, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    let mut normalized = PathBuf::new();
+    for component in Path::new(project_name).join(path).components() {
+        match component {
+            Component::ParentDir
+                if normalized
+                    .components()
+                    .next_back()
+                    .is_some_and(|c| c != Component::ParentDir) =>
+            {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized.to_string_lossy().replace('\\', "/")
+}
+
+fn paths(paths: &[String]) -> String {
+    paths
+        .iter()
+        .map(|p| path_escape(p))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A path in a `build` line, where spaces and colons are significant.
+fn path_escape(path: &str) -> String {
+    ninja_escape(path).replace(' ', "$ ").replace(':', "$:")
+}
+
+fn ninja_escape(text: &str) -> String {
+    text.replace('$', "$$")
+}
@@ -0,0 +1,332 @@
+// src/build_systems/plugin.rs
+use crate::build_systems::native::{
+    artifact_path, include_dirs, link_library, sources, workspace_dependencies,
+};
+use crate::build_systems::{BuildRequest, BuildSystem, GenerateContext};
+use crate::compiler::Compiler;
+use crate::config::Config;
+use crate::dependency_resolver::resolve_dependencies;
+use crate::package_manager::installed_version;
+use crate::path_lookup::{find_on_path, programs_with_prefix};
+use crate::project::Project;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub const PLUGIN_PREFIX: &str = "cpm-gen-";
+// Bumped when a field changes meaning or goes away; new fields may appear at any time
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A generator outside cpm: the executable configured under `[generators]`
+/// in config.toml, else `cpm-gen-<name>` on PATH. It is run from the
+/// workspace root as `<executable> generate|build|clean` with the workspace
+/// model as JSON on stdin.
+pub struct Plugin {
+    name: String,
+    executable: PathBuf,
+    description: String,
+}
+
+impl Plugin {
+    fn new(name: &str, executable: PathBuf) -> Self {
+        let description = format!("External generator {}", executable.display());
+        Plugin {
+            name: name.to_string(),
+            executable,
+            description,
+        }
+    }
+
+    /// The plugin providing `name`, if there is one.
+    pub fn find(name: &str) -> Option<Plugin> {
+        let configured = Config::load()
+            .ok()
+            .and_then(|config| config.generators.get(name).cloned());
+        match configured {
+            Some(executable) => Some(Plugin::new(name, PathBuf::from(executable))),
+            None => find_on_path(&format!("{}{}", PLUGIN_PREFIX, name))
+                .map(|path| Plugin::new(name, path)),
+        }
+    }
+
+    /// Every configured plugin and every `cpm-gen-*` on PATH, by name.
+    pub fn discover() -> Vec<Plugin> {
+        let mut executables = programs_with_prefix(PLUGIN_PREFIX);
+        if let Ok(config) = Config::load() {
+            for (name, executable) in config.generators {
+                executables.insert(name, PathBuf::from(executable));
+            }
+        }
+        executables
+            .into_iter()
+            .map(|(name, path)| Plugin::new(&name, path))
+            .collect()
+    }
+
+    fn run(&self, action: &str, request: &PluginRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let mut child = Command::new(&self.executable)
+            .arg(action)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.executable.display(), e))?;
+        let json = serde_json::to_vec(request)?;
+        // A plugin that exits without reading everything is reported by its status
+        let written = child.stdin.take().expect("stdin is piped").write_all(&json);
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!(
+                "{} {} failed with {}",
+                self.executable.display(),
+                action,
+                status
+            )
+            .into());
+        }
+        Ok(written?)
+    }
+}
+
+impl BuildSystem for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let request = PluginRequest::new(
+            project,
+            context.workspace,
+            compiler,
+            context.manifest_path,
+            context.configuration,
+        )?;
+        self.run("generate", &request)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let plugin_request = PluginRequest::new(
+            project,
+            request.workspace,
+            request.compiler,
+            request.manifest_path,
+            request.configuration,
+        )?;
+        self.run("build", &plugin_request)
+    }
+
+    fn clean(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let plugin_request = PluginRequest::new(
+            project,
+            request.workspace,
+            request.compiler,
+            request.manifest_path,
+            request.configuration,
+        )?;
+        self.run("clean", &plugin_request)
+    }
+}
+
+/// The document sent to a plugin. Paths inside a project are relative to its
+/// `directory`; the others are relative to `workspace_dir`.
+#[derive(Serialize)]
+struct PluginRequest<'a> {
+    protocol: u32,
+    cpm_version: &'static str,
+    project: &'a str, // The project to act on; the others are there for reference
+    configuration: Option<&'a str>, // Selected with `-c`, if it was
+    workspace_dir: String,
+    manifest_path: &'a str,
+    build_order: Vec<&'a str>,
+    compiler: CompilerModel<'a>,
+    projects: Vec<ProjectModel<'a>>,
+    packages: Vec<PackageModel>,
+}
+
+#[derive(Serialize)]
+struct CompilerModel<'a> {
+    family: &'static str,
+    command: &'a str,
+    version: &'a str,
+    target: &'a str,
+    archiver: &'a str,
+}
+
+#[derive(Serialize)]
+struct ProjectModel<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    project_type: &'a str,
+    language: &'a str,
+    version: Option<&'a str>,
+    directory: String,
+    build_systems: &'a [String],
+    output_dir: String,
+    artifact: String,
+    link_library: Option<String>,
+    sources: Vec<String>,
+    include_dirs: Vec<String>,
+    definitions: &'a [String],
+    configurations: Vec<ConfigurationModel<'a>>,
+    dependencies: Vec<&'a str>, // Workspace projects, direct and transitive, dependents first
+    packages: BTreeMap<&'a str, &'a str>, // Direct package dependencies and their requirements
+    custom_commands: Vec<CustomCommandModel<'a>>,
+}
+
+#[derive(Serialize)]
+struct ConfigurationModel<'a> {
+    name: &'a str,
+    compile_flags: Vec<String>,
+    link_flags: Vec<String>,
+    pre_build: Vec<&'a str>,
+    post_build: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct CustomCommandModel<'a> {
+    inputs: &'a [String],
+    outputs: &'a [String],
+    command: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct PackageModel {
+    name: String,
+    version: Option<String>, // None until installed
+    include_dir: String,
+    lib_dir: String,
+}
+
+impl<'a> PluginRequest<'a> {
+    fn new(
+        project: &'a Project,
+        workspace: &'a [Project],
+        compiler: &'a Compiler,
+        manifest_path: &'a str,
+        configuration: Option<&'a str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let workspace_dir = std::env::current_dir()?;
+        let mut packages: Vec<PackageModel> = Vec::new();
+        for member in workspace {
+            let mut names = member
+                .dependencies
+                .keys()
+                .filter(|name| !workspace.iter().any(|p| &p.name == *name))
+                .collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                if packages.iter().any(|p| &p.name == name) {
+                    continue;
+                }
+                let package_dir = format!("{}/{}", member.deps_dir(), name);
+                packages.push(PackageModel {
+                    name: name.clone(),
+                    version: installed_version(
+                        name,
+                        member.target.as_ref().map(|t| t.triple.as_str()),
+                    ),
+                    include_dir: format!("{}/include", package_dir),
+                    lib_dir: format!("{}/lib", package_dir),
+                });
+            }
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(PluginRequest {
+            protocol: PROTOCOL_VERSION,
+            cpm_version: env!("CARGO_PKG_VERSION"),
+            project: &project.name,
+            configuration,
+            workspace_dir: workspace_dir.to_string_lossy().to_string(),
+            manifest_path,
+            build_order: resolve_dependencies(workspace)
+                .into_iter()
+                .map(|p| p.name.as_str())
+                .collect(),
+            compiler: CompilerModel {
+                family: compiler.family_key(),
+                command: &compiler.command,
+                version: &compiler.version,
+                target: &compiler.target,
+                archiver: &compiler.archiver,
+            },
+            projects: workspace
+                .iter()
+                .map(|member| project_model(member, workspace, compiler, &workspace_dir))
+                .collect(),
+            packages,
+        })
+    }
+}
+
+fn project_model<'a>(
+    project: &'a Project,
+    workspace: &'a [Project],
+    compiler: &Compiler,
+    workspace_dir: &Path,
+) -> ProjectModel<'a> {
+    let project_dir = workspace_dir.join(&project.name);
+    ProjectModel {
+        name: &project.name,
+        project_type: &project.project_type,
+        language: &project.language,
+        version: project.version.as_deref(),
+        directory: project_dir.to_string_lossy().to_string(),
+        build_systems: &project.build_systems,
+        output_dir: project.output_dir(),
+        artifact: artifact_path(project, compiler),
+        link_library: link_library(project, compiler),
+        sources: sources(project, &project_dir),
+        include_dirs: include_dirs(project, workspace),
+        definitions: &project.preprocessor_definitions,
+        configurations: project
+            .configurations
+            .iter()
+            .map(|configuration| ConfigurationModel {
+                name: configuration,
+                compile_flags: compiler.compile_flags(project, configuration),
+                link_flags: compiler.link_flags(project, configuration),
+                pre_build: project.pre_build_commands(configuration),
+                post_build: project.post_build_commands(configuration),
+            })
+            .collect(),
+        dependencies: workspace_dependencies(project, workspace)
+            .into_iter()
+            .map(|p| p.name.as_str())
+            .collect(),
+        packages: project
+            .dependencies
+            .iter()
+            .filter(|(name, _)| !workspace.iter().any(|p| &p.name == *name))
+            .map(|(name, requirement)| (name.as_str(), requirement.as_str()))
+            .collect(),
+        custom_commands: project
+            .custom_commands
+            .iter()
+            .map(|custom| CustomCommandModel {
+                inputs: &custom.inputs,
+                outputs: &custom.outputs,
+                command: custom.expanded_command(),
+                message: custom.message(),
+            })
+            .collect(),
+    }
+}
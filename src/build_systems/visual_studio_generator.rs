@@ -1,106 +1,375 @@
 // src/build_systems/visual_studio_generator.rs
-use crate::project::Project;
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
+use crate::build_systems::native::include_dirs;
+use crate::build_systems::{
+    run_tool, to_workspace, uses_build_system, write_if_changed, BuildRequest, BuildSystem,
+    GenerateContext,
+};
+use crate::compiler::{Compiler, CompilerFamily};
+use crate::config_header::macro_name;
+use crate::dependency_resolver::resolve_dependencies;
+use crate::hooks::hook_environment;
+use crate::project::{Project, HEADER_EXTENSIONS, SOURCE_EXTENSIONS};
+use log::warn;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use uuid::Uuid;
 
-pub fn generate_visual_studio(project: &Project) -> std::io::Result<()> {
+// Project type GUIDs Visual Studio uses for C++ projects and solution folders
+const CPP_PROJECT_TYPE_GUID: &str = "8BC9CEB8-8B4A-11D0-8D11-00A0C91BC942";
+const SOLUTION_FOLDER_TYPE_GUID: &str = "2150E333-8FDC-42A3-9474-1A3956D46DE8";
+
+/// A `.vcxproj` per project and one solution for the workspace, built with MSBuild.
+pub struct VisualStudio;
+
+impl BuildSystem for VisualStudio {
+    fn name(&self) -> &str {
+        "VisualStudio"
+    }
+
+    fn description(&self) -> &str {
+        "Visual Studio projects in a workspace solution, built with MSBuild"
+    }
+
+    // MSBuild finds the compiler itself, so Visual Studio files can be generated anywhere
+    fn needs_compiler(&self) -> bool {
+        false
+    }
+
+    fn generate(
+        &self,
+        project: &Project,
+        context: &GenerateContext,
+        compiler: &Compiler,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if project.target.is_some() {
+            warn!(
+                "{}: Visual Studio targets come from `platforms`; skipping it for --target",
+                project.name
+            );
+            return Ok(());
+        }
+        generate_visual_studio(project, context, compiler)
+    }
+
+    fn build(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = solution_target(project);
+        run_tool(
+            &mut msbuild(project, request, &target),
+            &format!("Build of {}", project.name),
+        )
+    }
+
+    fn clean(
+        &self,
+        project: &Project,
+        request: &BuildRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = format!("{}:Clean", solution_target(project));
+        run_tool(
+            &mut msbuild(project, request, &target),
+            &format!("Clean of {}", project.name),
+        )
+    }
+
+    // Platforms replace --target, so outputs never go in a per-triple directory
+    fn output_dir(&self, project: &Project, request: &BuildRequest) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            project.name,
+            project.structure.output_dir,
+            platform(project),
+            request.configuration(project)
+        )
+    }
+}
+
+/// MSBuild on `target` in the workspace solution, for the selected
+/// configuration and the project's first platform. Building a project builds
+/// the projects it references first.
+fn msbuild(project: &Project, request: &BuildRequest, target: &str) -> Command {
+    let msbuild = find_msbuild_executable().unwrap_or_else(|_| PathBuf::from("msbuild.exe"));
+    let mut command = Command::new(msbuild);
+    command
+        .arg(solution_path(request.workspace))
+        .arg(format!("/t:{}", target))
+        .arg(format!(
+            "/p:Configuration={}",
+            request.configuration(project)
+        ))
+        .arg(format!("/p:Platform={}", platform(project)));
+    command
+}
+
+fn platform(project: &Project) -> &str {
+    project.platforms.first().map_or("x64", String::as_str)
+}
+
+/// The workspace solution, named after the first project in the manifest
+/// rather than the directory, which differs between checkouts.
+pub fn solution_path(workspace: &[Project]) -> PathBuf {
+    let name = workspace
+        .first()
+        .map_or_else(|| "workspace".to_string(), project_file_stem);
+    PathBuf::from(format!("{}.sln", name))
+}
+
+/// `<project>/<last component of its name>.vcxproj`, relative to the workspace.
+fn vcxproj_path(project: &Project) -> PathBuf {
+    Path::new(&project.name).join(format!("{}.vcxproj", project_file_stem(project)))
+}
+
+fn project_file_stem(project: &Project) -> String {
+    Path::new(&project.name)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The name of the project's target in the solution: its folder path and
+/// name, with the characters MSBuild doesn't allow replaced.
+fn solution_target(project: &Project) -> String {
+    project
+        .name
+        .split('/')
+        .map(|part| part.replace(['.', '(', ')', '$', '@', '%', ';', '\'', ' '], "_"))
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+// Function to locate msbuild.exe
+fn find_msbuild_executable() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    // Common installation paths for msbuild.exe
+    let possible_paths = vec![
+        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Community\MSBuild\Current\Bin\MSBuild.exe",
+        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Professional\MSBuild\Current\Bin\MSBuild.exe",
+        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Enterprise\MSBuild\Current\Bin\MSBuild.exe",
+        r"C:\Program Files\Microsoft Visual Studio\2022\Community\MSBuild\Current\Bin\MSBuild.exe",
+        r"C:\Program Files\Microsoft Visual Studio\2022\Professional\MSBuild\Current\Bin\MSBuild.exe",
+        r"C:\Program Files\Microsoft Visual Studio\2022\Enterprise\MSBuild\Current\Bin\MSBuild.exe",
+    ];
+
+    for path_str in possible_paths {
+        let path = PathBuf::from(path_str);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+
+    Err("msbuild.exe not found. Please ensure Visual Studio is installed, or add msbuild.exe to your PATH.".into())
+}
+
+/// Writes the project's `.vcxproj`, then the workspace solution with every
+/// Visual Studio project.
+pub fn generate_visual_studio(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+) -> Result<(), Box<dyn std::error::Error>> {
     let project_dir = &project.name;
     fs::create_dir_all(project_dir)?;
 
     // Create project structure directories
     let src_path = Path::new(project_dir).join(&project.structure.src_dir);
     let include_path = Path::new(project_dir).join(&project.structure.include_dir);
-    let output_path = Path::new(project_dir).join(&project.structure.output_dir);
-    let intermediate_path = output_path.join("Intermediate");
 
     fs::create_dir_all(&src_path)?;
     fs::create_dir_all(&include_path)?;
-    fs::create_dir_all(&output_path)?;
-    fs::create_dir_all(&intermediate_path)?;
-
-    // Generate .sln file
-    let project_guid = Uuid::new_v4();
-    let vs_version = &project.visual_studio_version;
-    let (format_version, vs_version_name, platform_toolset) = match vs_version.as_str() {
-        "15" => ("12.00", "# Visual Studio 15", "v141"), // Visual Studio 2017
-        "16" => ("12.00", "# Visual Studio Version 16", "v142"), // Visual Studio 2019
-        "17" => ("12.00", "# Visual Studio Version 17", "v143"), // Visual Studio 2022
-        _ => ("12.00", "# Visual Studio Version 17", "v143"),    // Default to VS 2022
-    };
 
-    // Correct C++ Project Type GUID
-    let cpp_project_type_guid = "8BC9CEB8-8B4A-11D0-8D11-00A0C91BC942";
+    let vs_projects = resolve_dependencies(context.workspace)
+        .into_iter()
+        .filter(|p| uses_build_system(p, context, "VisualStudio"))
+        .collect::<Vec<_>>();
+    let mut dependencies = project.dependencies.keys().collect::<Vec<_>>();
+    dependencies.sort();
+    for name in dependencies {
+        if context.workspace.iter().any(|p| &p.name == name)
+            && !vs_projects.iter().any(|p| &p.name == name)
+        {
+            warn!(
+                "{}: {} isn't built with VisualStudio, so the solution neither builds nor links it",
+                project.name, name
+            );
+        }
+    }
+    let resource = write_version_resource(project)?;
+    let items = project_items(project, resource.as_deref());
+    write_if_changed(
+        &vcxproj_path(project),
+        &vcxproj(project, context.workspace, &vs_projects, compiler, &items),
+    )?;
+    let mut filters_path = vcxproj_path(project).into_os_string();
+    filters_path.push(".filters");
+    write_if_changed(Path::new(&filters_path), &generate_filters(project, &items))?;
+
+    // The solution can only have one version, so the first project decides
+    let solution_version = vs_projects
+        .first()
+        .map_or(project.visual_studio_version.as_str(), |p| {
+            p.visual_studio_version.as_str()
+        });
+    write_if_changed(
+        &solution_path(context.workspace),
+        &solution(&vs_projects, solution_version),
+    )?;
+    Ok(())
+}
 
-    // Generate project configurations for .sln
-    let mut solution_configurations = String::new();
+/// The solution: each generated project, in a solution folder per directory
+/// above it, and every configuration and platform any of them has.
+fn solution(projects: &[&Project], vs_version: &str) -> String {
+    let vs_version_name = match vs_version {
+        "15" => "# Visual Studio 15",
+        "16" => "# Visual Studio Version 16",
+        _ => "# Visual Studio Version 17",
+    };
+    let mut project_entries = String::new();
+    let mut folders: Vec<(String, String)> = Vec::new();
+    let mut nested_projects = String::new();
+    let mut solution_configurations = Vec::new();
     let mut project_configuration_platforms = String::new();
-    for platform in &project.platforms {
-        for configuration in &project.configurations {
-            solution_configurations.push_str(&format!(
-                "        {0}|{1} = {0}|{1}\n",
-                configuration, platform
-            ));
-            project_configuration_platforms.push_str(&format!(
-                "        {{{project_guid}}}.{0}|{1}.ActiveCfg = {0}|{1}\n",
-                configuration, platform,
-                project_guid = project_guid
-            ));
-            project_configuration_platforms.push_str(&format!(
-                "        {{{project_guid}}}.{0}|{1}.Build.0 = {0}|{1}\n",
-                configuration, platform,
-                project_guid = project_guid
-            ));
+    for project in projects {
+        let guid = project_guid(project);
+        project_entries.push_str(&format!(
+            "Project(\"{{{}}}\") = \"{}\", \"{}\", \"{{{}}}\"\nEndProject\n",
+            CPP_PROJECT_TYPE_GUID,
+            project_file_stem(project),
+            to_windows_path(&vcxproj_path(project).to_string_lossy()),
+            guid
+        ));
+
+        let mut parent = None;
+        let mut folder_path = String::new();
+        for folder in Path::new(&project.name)
+            .parent()
+            .into_iter()
+            .flat_map(|p| p.iter())
+        {
+            let folder = folder.to_string_lossy().to_string();
+            folder_path = if folder_path.is_empty() {
+                folder.clone()
+            } else {
+                format!("{}/{}", folder_path, folder)
+            };
+            let folder_guid = match folders.iter().find(|(path, _)| *path == folder_path) {
+                Some((_, guid)) => guid.clone(),
+                None => {
+                    let guid = stable_guid("folder", &folder_path);
+                    project_entries.push_str(&format!(
+                        "Project(\"{{{}}}\") = \"{1}\", \"{1}\", \"{{{2}}}\"\nEndProject\n",
+                        SOLUTION_FOLDER_TYPE_GUID, folder, guid
+                    ));
+                    if let Some(parent) = &parent {
+                        nested_projects
+                            .push_str(&format!("        {{{}}} = {{{}}}\n", guid, parent));
+                    }
+                    folders.push((folder_path.clone(), guid.clone()));
+                    guid
+                }
+            };
+            parent = Some(folder_guid);
+        }
+        if let Some(parent) = parent {
+            nested_projects.push_str(&format!("        {{{}}} = {{{}}}\n", guid, parent));
+        }
+
+        for platform in &project.platforms {
+            for configuration in &project.configurations {
+                let solution_configuration = format!("{}|{}", configuration, platform);
+                project_configuration_platforms.push_str(&format!(
+                    "        {{{0}}}.{1}.ActiveCfg = {1}\n        {{{0}}}.{1}.Build.0 = {1}\n",
+                    guid, solution_configuration
+                ));
+                if !solution_configurations.contains(&solution_configuration) {
+                    solution_configurations.push(solution_configuration);
+                }
+            }
         }
     }
 
-    let solution_content = format!(
-        r#"Microsoft Visual Studio Solution File, Format Version {}
-{}
-Project("{{{project_type_guid}}}") = "{project_name}", "{project_name}.vcxproj", "{{{project_guid}}}"
-EndProject
-Global
-    GlobalSection(SolutionConfigurationPlatforms) = preSolution
-{solution_configurations}
-    EndGlobalSection
-    GlobalSection(ProjectConfigurationPlatforms) = postSolution
-{project_configuration_platforms}
-    EndGlobalSection
-EndGlobal
-"#,
-        format_version,
-        vs_version_name,
-        project_type_guid = cpp_project_type_guid,
-        project_name = project.name,
-        project_guid = project_guid,
-        solution_configurations = solution_configurations,
-        project_configuration_platforms = project_configuration_platforms
+    let mut content = format!(
+        "Microsoft Visual Studio Solution File, Format Version 12.00\n{}\n{}Global\n",
+        vs_version_name, project_entries
     );
+    content.push_str("    GlobalSection(SolutionConfigurationPlatforms) = preSolution\n");
+    for configuration in &solution_configurations {
+        content.push_str(&format!("        {0} = {0}\n", configuration));
+    }
+    content.push_str("    EndGlobalSection\n");
+    content.push_str("    GlobalSection(ProjectConfigurationPlatforms) = postSolution\n");
+    content.push_str(&project_configuration_platforms);
+    content.push_str("    EndGlobalSection\n");
+    if !nested_projects.is_empty() {
+        content.push_str("    GlobalSection(NestedProjects) = preSolution\n");
+        content.push_str(&nested_projects);
+        content.push_str("    EndGlobalSection\n");
+    }
+    content.push_str("EndGlobal\n");
+    content
+}
 
-    let sln_path = format!("{}/{}.sln", project_dir, project.name);
-    let mut sln_file = File::create(&sln_path)?;
-    sln_file.write_all(solution_content.as_bytes())?;
-
-    // Generate .vcxproj file
-    let mut includes = vec![project.structure.include_dir.clone()];
-    includes.extend(project.additional_include_dirs.clone());
-    includes.extend(
-        project
-            .dependencies
-            .keys()
-            .map(|dep| format!("..\\deps\\{}\\include", dep)),
-    );
+fn project_guid(project: &Project) -> String {
+    stable_guid("project", &project.name)
+}
+
+/// A GUID derived from `name` as the manifest spells it, so regenerating,
+/// even in another checkout, keeps it and solution user settings keyed on it
+/// survive. Upper case, as Visual Studio writes GUIDs when it saves.
+fn stable_guid(kind: &str, name: &str) -> String {
+    let key = format!("cpm:{}:{}", kind, name);
+    format!("{:X}", Uuid::new_v5(&Uuid::NAMESPACE_URL, key.as_bytes()))
+}
+
+fn platform_toolset<'a>(project: &'a Project, compiler: &Compiler) -> &'a str {
+    // clang-cl ships with Visual Studio as its own toolset
+    if compiler.family == CompilerFamily::Clang {
+        return "ClangCL";
+    }
+    match project.visual_studio_version.as_str() {
+        "15" => "v141", // Visual Studio 2017
+        "16" => "v142", // Visual Studio 2019
+        _ => "v143",    // Visual Studio 2022, the default
+    }
+}
+
+/// The `.vcxproj` for `project`, listing `items`.
+fn vcxproj(
+    project: &Project,
+    workspace: &[Project],
+    vs_projects: &[&Project],
+    compiler: &Compiler,
+    items: &[ProjectItem],
+) -> String {
+    let platform_toolset = platform_toolset(project, compiler);
+    let includes = include_dirs(project, workspace)
+        .iter()
+        .map(|dir| to_windows_path(dir))
+        .collect::<Vec<_>>();
     let additional_includes = includes.join(";");
 
-    let mut libraries = Vec::new();
-    libraries.extend(
-        project
-            .dependencies
-            .keys()
-            .map(|dep| format!("..\\deps\\{}\\lib", dep)),
-    );
+    // Workspace libraries come through project references instead
+    let mut libraries = project
+        .dependencies
+        .keys()
+        .filter(|dep| !workspace.iter().any(|p| &p.name == *dep))
+        .map(|dep| {
+            let lib_dir = format!("{}/{}/lib", project.deps_dir(), dep);
+            format!(
+                "{}{}",
+                to_windows_path(&to_workspace(project)),
+                to_windows_path(&lib_dir)
+            )
+        })
+        .collect::<Vec<_>>();
+    libraries.sort();
     let additional_libraries = libraries.join(";");
+    let project_references = generate_project_references(project, workspace, vs_projects);
 
     let preprocessor_definitions = if !project.preprocessor_definitions.is_empty() {
         project.preprocessor_definitions.join(";") + ";%(PreprocessorDefinitions)"
@@ -108,23 +377,36 @@ EndGlobal
         "%(PreprocessorDefinitions)".to_string()
     };
 
-    let compiler_flags = project.compiler_flags.join(" ");
-    let linker_flags = project.linker_flags.join(" ");
-
-    // Generate ItemGroup for source and header files
-    let source_files = format!(
-        r#"<ClCompile Include="{}\\**\\*.cpp" />"#,
-        project.structure.src_dir
-    );
+    // MSBuild drives cl or clang-cl, which both take MSVC-style flags
+    let compiler_flags = project.compiler_flags.for_family("msvc").join(" ");
+    let linker_flags = project.linker_flags.for_family("msvc").join(" ");
 
-    let header_files = format!(
-        r#"<ClInclude Include="{}\\**\\*.h" />"#,
-        project.structure.include_dir
-    );
+    // Files are listed rather than matched with wildcards so the filters
+    // file can place each one, and so generated files are there before they exist
+    let item_lines = |element: &str| {
+        items
+            .iter()
+            .filter(|item| item.element == element)
+            .map(|item| {
+                format!(
+                    "    <{} Include=\"{}\" />\n",
+                    element,
+                    xml_escape(&to_windows_path(&item.path))
+                )
+            })
+            .collect::<String>()
+    };
+    let source_files = item_lines("ClCompile");
+    let header_files = item_lines("ClInclude");
+    let resource_files = item_lines("ResourceCompile");
+    let custom_build_items = generate_custom_build_items(project);
 
     // Generate PropertyGroups and ItemDefinitionGroups for each configuration and platform
     let mut property_groups = String::new();
+    let mut output_groups = String::new();
     let mut item_definition_groups = String::new();
+    let output_name = project.output_stem();
+    let output_dir = to_windows_path(&project.structure.output_dir);
     for platform in &project.platforms {
         for configuration in &project.configurations {
             // PropertyGroup
@@ -133,16 +415,17 @@ EndGlobal
             } else {
                 "false"
             };
-            let whole_program_optimization = if configuration.to_lowercase() == "release" {
-                "true"
-            } else {
-                "false"
-            };
-            let optimization = if configuration.to_lowercase() == "release" {
-                "MaxSpeed"
-            } else {
-                "Disabled"
-            };
+            let options = project.options_for(configuration);
+            // Release keeps whole-program optimization unless `lto` says otherwise
+            let whole_program_optimization = project
+                .options
+                .lto
+                .unwrap_or(configuration.eq_ignore_ascii_case("release"));
+            let compile_properties = compiler
+                .msbuild_compile_properties(project, configuration)
+                .into_iter()
+                .map(|(name, value)| format!("      <{0}>{1}</{0}>\n", name, value))
+                .collect::<String>();
 
             property_groups.push_str(&format!(
                 r#"<PropertyGroup Condition="'$(Configuration)|$(Platform)'=='{configuration}|{platform}'" Label="Configuration">
@@ -162,50 +445,63 @@ EndGlobal
                 character_set = project.character_set,
             ));
 
+            // Each configuration and platform gets its own output and
+            // intermediate directories, so builds don't overwrite each other
+            output_groups.push_str(&format!(
+                r#"<PropertyGroup Condition="'$(Configuration)|$(Platform)'=='{configuration}|{platform}'">
+    <OutDir>$(MSBuildProjectDirectory)\{output_dir}\{platform}\{configuration}\</OutDir>
+    <IntDir>$(MSBuildProjectDirectory)\{output_dir}\Intermediate\{platform}\{configuration}\</IntDir>
+    <TargetName>{output_name}</TargetName>
+    <TargetExt>{target_extension}</TargetExt>
+    <LinkIncremental>false</LinkIncremental>
+  </PropertyGroup>
+"#,
+                configuration = configuration,
+                platform = platform,
+                output_dir = output_dir,
+                output_name = output_name,
+                target_extension = target_extension(&project.project_type),
+            ));
+
             // ItemDefinitionGroup
             item_definition_groups.push_str(&format!(
                 r#"<ItemDefinitionGroup Condition="'$(Configuration)|$(Platform)'=='{configuration}|{platform}'">
     <ClCompile>
-      <WarningLevel>Level3</WarningLevel>
-      <Optimization>{optimization}</Optimization>
-      <PreprocessorDefinitions>{preprocessor_definitions}</PreprocessorDefinitions>
+{compile_properties}      <PreprocessorDefinitions>{preprocessor_definitions}</PreprocessorDefinitions>
       <AdditionalIncludeDirectories>{includes};%(AdditionalIncludeDirectories)</AdditionalIncludeDirectories>
       <AdditionalOptions>{compiler_flags} %(AdditionalOptions)</AdditionalOptions>
       <LanguageStandard>{language_standard}</LanguageStandard>
     </ClCompile>
     <Link>
-      <SubSystem>{subsystem}</SubSystem>
-      <GenerateDebugInformation>true</GenerateDebugInformation>
+{link_outputs}      <GenerateDebugInformation>{debug_info}</GenerateDebugInformation>
       <AdditionalLibraryDirectories>{libraries};%(AdditionalLibraryDirectories)</AdditionalLibraryDirectories>
-      <AdditionalDependencies>kernel32.lib;user32.lib;{}</AdditionalDependencies>
+      <AdditionalDependencies>kernel32.lib;user32.lib;%(AdditionalDependencies)</AdditionalDependencies>
       <AdditionalOptions>{linker_flags} %(AdditionalOptions)</AdditionalOptions>
     </Link>
-  </ItemDefinitionGroup>
+{pre_build_event}{post_build_event}  </ItemDefinitionGroup>
 "#,
                 configuration = configuration,
                 platform = platform,
-                optimization = optimization,
+                compile_properties = compile_properties,
+                debug_info = options.debug_info,
                 preprocessor_definitions = preprocessor_definitions,
                 includes = additional_includes,
                 compiler_flags = compiler_flags,
                 language_standard = map_language_standard(&project.language),
-                subsystem = map_subsystem(&project.project_type),
+                link_outputs = link_outputs(&project.project_type),
                 libraries = additional_libraries,
                 linker_flags = linker_flags,
-                // You can add more default dependencies if needed
+                pre_build_event = build_event("PreBuildEvent", project, &project.pre_build_commands(configuration)),
+                post_build_event = build_event("PostBuildEvent", project, &project.post_build_commands(configuration)),
             ));
         }
     }
 
-    let output_name = project.output_name.clone().unwrap_or_else(|| project.name.clone());
-
     // Generate ProjectConfigurations and PropertySheets
-    let project_configurations = generate_project_configurations(&project);
-    let property_sheets = generate_property_sheets(&project);
-
-    let solution_dir = Path::new(project_dir).canonicalize()?.to_str().unwrap().to_string();
+    let project_configurations = generate_project_configurations(project);
+    let property_sheets = generate_property_sheets(project);
 
-    let project_content = format!(
+    format!(
         r#"<Project DefaultTargets="Build" xmlns="http://schemas.microsoft.com/developer/msbuild/2003">
   <ItemGroup Label="ProjectConfigurations">
 {project_configurations}
@@ -224,43 +520,215 @@ EndGlobal
   </ImportGroup>
 {property_sheets}
   <PropertyGroup Label="UserMacros" />
-  <PropertyGroup>
-    <OutDir>{solution_dir}\\{output_dir}\\</OutDir>
-    <IntDir>{solution_dir}\\{output_dir}\\Intermediate\\</IntDir>
-    <TargetName>{output_name}</TargetName>
-    <TargetPath>{solution_dir}\\{output_dir}\\{output_name}.exe</TargetPath>
-    <LinkIncremental>false</LinkIncremental>
-  </PropertyGroup>
-{item_definition_groups}
+{output_groups}{item_definition_groups}
   <ItemGroup>
-    {source_files}
-  </ItemGroup>
+{source_files}  </ItemGroup>
   <ItemGroup>
-    {header_files}
-  </ItemGroup>
-  <Import Project="$(VCTargetsPath)\Microsoft.Cpp.targets" />
+{header_files}  </ItemGroup>
+  <ItemGroup>
+{resource_files}{custom_build_items}  </ItemGroup>
+{project_references}  <Import Project="$(VCTargetsPath)\Microsoft.Cpp.targets" />
   <ImportGroup Label="ExtensionTargets">
   </ImportGroup>
 </Project>
 "#,
-        project_guid = project_guid,
+        project_guid = project_guid(project),
         project_name = project.name,
         project_configurations = project_configurations,
         property_groups = property_groups,
         property_sheets = property_sheets,
-        solution_dir = solution_dir,
-        output_dir = project.structure.output_dir,
-        output_name = output_name,
+        output_groups = output_groups,
         item_definition_groups = item_definition_groups,
         source_files = source_files,
         header_files = header_files,
-    );
+        resource_files = resource_files,
+        custom_build_items = custom_build_items,
+        project_references = project_references,
+    )
+}
 
-    let proj_path = format!("{}/{}.vcxproj", project_dir, project.name);
-    let mut proj_file = File::create(&proj_path)?;
-    proj_file.write_all(project_content.as_bytes())?;
+/// A `ProjectReference` per workspace dependency built with Visual Studio,
+/// which MSBuild builds first and links against.
+fn generate_project_references(
+    project: &Project,
+    workspace: &[Project],
+    vs_projects: &[&Project],
+) -> String {
+    let mut dependencies = project
+        .dependencies
+        .keys()
+        .filter_map(|name| {
+            vs_projects
+                .iter()
+                .find(|p| &p.name == name && workspace.iter().any(|w| &w.name == name))
+        })
+        .collect::<Vec<_>>();
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut references = String::new();
+    for dependency in dependencies {
+        references.push_str(&format!(
+            "    <ProjectReference Include=\"{}{}\">\n      <Project>{{{}}}</Project>\n    </ProjectReference>\n",
+            to_windows_path(&to_workspace(project)),
+            to_windows_path(&vcxproj_path(dependency).to_string_lossy()),
+            project_guid(dependency)
+        ));
+    }
+    if references.is_empty() {
+        return String::new();
+    }
+    format!("  <ItemGroup>\n{}  </ItemGroup>\n", references)
+}
 
-    Ok(())
+/// A file in the `.vcxproj`, relative to the project directory, and the
+/// filter Visual Studio shows it under.
+struct ProjectItem {
+    element: &'static str,
+    path: String,
+    filter: String,
+}
+
+/// Sources and headers found under `src_dir` and `include_dir`, each in a
+/// filter mirroring its directory, then generated files, the version
+/// resource and custom command inputs. Sorted, so output is stable.
+fn project_items(project: &Project, resource: Option<&str>) -> Vec<ProjectItem> {
+    let mut generated = project
+        .generated_sources()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    generated.extend(project.generated_headers().into_iter().map(String::from));
+    generated.extend(project.config_header_path());
+    generated.extend(project.version_header_path());
+
+    let mut items = Vec::new();
+    let trees = [
+        (&project.structure.src_dir, "Source Files"),
+        (&project.structure.include_dir, "Header Files"),
+    ];
+    for (dir, filter) in trees {
+        for path in files_under(&Path::new(&project.name).join(dir)) {
+            let relative = Path::new(dir)
+                .join(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let element = match item_element(&relative) {
+                Some(element) if !generated.contains(&relative) => element,
+                _ => continue,
+            };
+            let subdir = Path::new(&path)
+                .parent()
+                .map(|p| to_windows_path(&p.to_string_lossy()))
+                .unwrap_or_default();
+            let filter = if subdir.is_empty() {
+                filter.to_string()
+            } else {
+                format!("{}\\{}", filter, subdir)
+            };
+            if !items.iter().any(|item: &ProjectItem| item.path == relative) {
+                items.push(ProjectItem {
+                    element,
+                    path: relative,
+                    filter,
+                });
+            }
+        }
+    }
+    for path in generated {
+        if let Some(element) = item_element(&path) {
+            items.push(ProjectItem {
+                element,
+                path,
+                filter: "Generated Files".to_string(),
+            });
+        }
+    }
+    if let Some(resource) = resource {
+        items.push(ProjectItem {
+            element: "ResourceCompile",
+            path: resource.to_string(),
+            filter: "Resource Files".to_string(),
+        });
+    }
+    items.sort_by(|a, b| (a.element, &a.path).cmp(&(b.element, &b.path)));
+    for custom in &project.custom_commands {
+        if let Some(input) = custom.inputs.first() {
+            items.push(ProjectItem {
+                element: "CustomBuild",
+                path: input.clone(),
+                filter: "Resource Files".to_string(),
+            });
+        }
+    }
+    items
+}
+
+/// Files below `dir`, relative to it, in a stable order.
+fn files_under(dir: &Path) -> Vec<String> {
+    let pattern = dir.join("**").join("*");
+    let mut files = glob::glob(&pattern.to_string_lossy())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            path.strip_prefix(dir)
+                .ok()
+                .map(|p| p.to_string_lossy().replace('\\', "/"))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn item_element(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    if SOURCE_EXTENSIONS.contains(&extension.as_str()) {
+        Some("ClCompile")
+    } else if HEADER_EXTENSIONS.contains(&extension.as_str()) {
+        Some("ClInclude")
+    } else {
+        None
+    }
+}
+
+/// The `.vcxproj.filters` file: every filter the items use, with their
+/// parents, then each item with its filter.
+fn generate_filters(project: &Project, items: &[ProjectItem]) -> String {
+    let mut filters = BTreeSet::new();
+    for item in items {
+        let mut filter = item.filter.as_str();
+        filters.insert(filter);
+        while let Some((parent, _)) = filter.rsplit_once('\\') {
+            filters.insert(parent);
+            filter = parent;
+        }
+    }
+    let mut content = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    content.push_str("<Project ToolsVersion=\"4.0\" xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\n");
+    content.push_str("  <ItemGroup>\n");
+    for filter in filters {
+        content.push_str(&format!(
+            "    <Filter Include=\"{}\">\n      <UniqueIdentifier>{{{}}}</UniqueIdentifier>\n    </Filter>\n",
+            xml_escape(filter),
+            stable_guid("filter", &format!("{}/{}", project.name, filter))
+        ));
+    }
+    content.push_str("  </ItemGroup>\n");
+    content.push_str("  <ItemGroup>\n");
+    for item in items {
+        content.push_str(&format!(
+            "    <{0} Include=\"{1}\">\n      <Filter>{2}</Filter>\n    </{0}>\n",
+            item.element,
+            xml_escape(&to_windows_path(&item.path)),
+            xml_escape(&item.filter)
+        ));
+    }
+    content.push_str("  </ItemGroup>\n");
+    content.push_str("</Project>\n");
+    content
 }
 
 fn generate_project_configurations(project: &Project) -> String {
@@ -300,6 +768,192 @@ fn generate_property_sheets(project: &Project) -> String {
     property_sheets
 }
 
+/// Each custom command hangs off its first input as a `CustomBuild` item so
+/// MSBuild reruns it only when an input is newer than the outputs.
+fn generate_custom_build_items(project: &Project) -> String {
+    let mut items = String::new();
+    for custom in &project.custom_commands {
+        let (primary, additional) = match custom.inputs.split_first() {
+            Some(split) => split,
+            None => {
+                warn!(
+                    "Custom command '{}' has no inputs; Visual Studio can't schedule it",
+                    custom.command
+                );
+                continue;
+            }
+        };
+        let join = |paths: &[String]| {
+            paths
+                .iter()
+                .map(|p| to_windows_path(p))
+                .collect::<Vec<_>>()
+                .join(";")
+        };
+        let mut command = Vec::new();
+        for output in &custom.outputs {
+            if let Some(dir) = Path::new(output)
+                .parent()
+                .filter(|d| !d.as_os_str().is_empty())
+            {
+                let dir = to_windows_path(&dir.to_string_lossy());
+                let mkdir = format!("if not exist \"{0}\" mkdir \"{0}\"", dir);
+                if !command.contains(&mkdir) {
+                    command.push(mkdir);
+                }
+            }
+        }
+        command.push(custom.expanded_command());
+        items.push_str(&format!(
+            r#"    <CustomBuild Include="{input}">
+      <Command>{command}</Command>
+      <Message>{message}</Message>
+      <Outputs>{outputs}</Outputs>
+      <AdditionalInputs>{additional_inputs}</AdditionalInputs>
+      <LinkObjects>false</LinkObjects>
+    </CustomBuild>
+"#,
+            input = to_windows_path(primary),
+            command = xml_escape(&command.join("\r\n")),
+            message = xml_escape(&custom.message()),
+            outputs = join(&custom.outputs),
+            additional_inputs = join(additional),
+        ));
+    }
+    items
+}
+
+/// Writes a `VERSIONINFO` resource next to the version header, whose macros it
+/// uses, and returns its path relative to the project directory. Static
+/// libraries have no image to attach it to.
+fn write_version_resource(project: &Project) -> std::io::Result<Option<String>> {
+    let header = match project.version_header_path() {
+        Some(header) if project.project_type != "StaticLib" => header,
+        _ => return Ok(None),
+    };
+    let resource = Path::new(&header)
+        .with_extension("rc")
+        .to_string_lossy()
+        .to_string();
+    let name = macro_name(&project.name);
+    let output_name = project.output_stem();
+    let (extension, file_type) = if project.project_type == "SharedLib" {
+        ("dll", "VFT_DLL")
+    } else {
+        ("exe", "VFT_APP")
+    };
+
+    let mut strings = vec![
+        (
+            "FileDescription",
+            project
+                .description
+                .clone()
+                .unwrap_or_else(|| project.name.clone()),
+        ),
+        ("ProductName", project.name.clone()),
+        ("InternalName", project.name.clone()),
+        ("OriginalFilename", format!("{}.{}", output_name, extension)),
+    ];
+    if !project.authors.is_empty() {
+        strings.push(("CompanyName", project.authors.join(", ")));
+    }
+    if let Some(license) = &project.license {
+        strings.push(("LegalCopyright", format!("Licensed under {}", license)));
+    }
+    let string_lines = strings
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "            VALUE \"{}\", \"{}\"",
+                key,
+                value.replace('"', "\"\"")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let content = format!(
+        r#"/* Generated by cpm from the project version. Do not edit. */
+#include <winver.h>
+#include "{header_name}"
+
+VS_VERSION_INFO VERSIONINFO
+FILEVERSION {name}_VERSION_MAJOR,{name}_VERSION_MINOR,{name}_VERSION_PATCH,0
+PRODUCTVERSION {name}_VERSION_MAJOR,{name}_VERSION_MINOR,{name}_VERSION_PATCH,0
+FILEFLAGSMASK VS_FFI_FILEFLAGSMASK
+FILEFLAGS 0
+FILEOS VOS_NT_WINDOWS32
+FILETYPE {file_type}
+FILESUBTYPE VFT2_UNKNOWN
+BEGIN
+    BLOCK "StringFileInfo"
+    BEGIN
+        BLOCK "040904b0"
+        BEGIN
+{string_lines}
+            VALUE "FileVersion", {name}_VERSION
+            VALUE "ProductVersion", {name}_VERSION
+        END
+    END
+    BLOCK "VarFileInfo"
+    BEGIN
+        VALUE "Translation", 0x409, 1200
+    END
+END
+"#,
+        header_name = Path::new(&header)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        name = name,
+        file_type = file_type,
+        string_lines = string_lines,
+    );
+    let path = Path::new(&project.name).join(&resource);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_if_changed(&path, &content)?;
+    Ok(Some(resource))
+}
+
+fn to_windows_path(path: &str) -> String {
+    path.replace('/', "\\")
+}
+
+fn build_event(element: &str, project: &Project, commands: &[&str]) -> String {
+    if commands.is_empty() {
+        return String::new();
+    }
+    let mut lines = hook_environment(
+        project,
+        "$(MSBuildProjectDirectory)",
+        "$(Configuration)",
+        '\\',
+    )
+    .into_iter()
+    .map(|(name, value)| format!("set {}={}", name, value))
+    .collect::<Vec<_>>();
+    // MSBuild only checks the exit code of the whole script, so stop at the first failure
+    for command in commands {
+        lines.push(command.to_string());
+        lines.push("if errorlevel 1 exit /b 1".to_string());
+    }
+    format!(
+        "    <{element}>\n      <Command>{command}</Command>\n    </{element}>\n",
+        element = element,
+        command = xml_escape(&lines.join("\r\n")),
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 fn map_configuration_type(project_type: &str) -> &str {
     match project_type {
         "Console" => "Application",
@@ -325,11 +979,281 @@ fn map_language_standard(language: &str) -> &str {
     }
 }
 
-fn map_subsystem(project_type: &str) -> &str {
+fn target_extension(project_type: &str) -> &str {
     match project_type {
-        "Console" => "Console",
-        "SharedLib" => "Windows",
-        "StaticLib" => "Console", // Static libraries don't produce an executable
-        _ => "Console",
+        "StaticLib" => ".lib",
+        "SharedLib" => ".dll",
+        _ => ".exe",
+    }
+}
+
+/// Link settings that depend on what the project produces: the subsystem
+/// for executables and the import library next to a DLL.
+fn link_outputs(project_type: &str) -> &str {
+    match project_type {
+        "StaticLib" => "",
+        "SharedLib" => "      <ImportLibrary>$(OutDir)$(TargetName).lib</ImportLibrary>\n",
+        "Windowed" => "      <SubSystem>Windows</SubSystem>\n",
+        _ => "      <SubSystem>Console</SubSystem>\n",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectsFile;
+    use roxmltree::{Document, Node};
+
+    const MANIFEST: &str = r#"
+        [[projects]]
+        name = "app"
+        type = "Console"
+        compiler = "MSVC"
+        language = "C++17"
+        platforms = ["x64", "Win32"]
+        dependencies = { gui = "*", core = "*", plugin = "*" }
+
+        [[projects]]
+        name = "gui"
+        type = "Windowed"
+        compiler = "MSVC"
+        language = "C++17"
+        platforms = ["x64", "Win32"]
+
+        [[projects]]
+        name = "core"
+        type = "StaticLib"
+        compiler = "MSVC"
+        language = "C++17"
+        platforms = ["x64", "Win32"]
+
+        [[projects]]
+        name = "plugin"
+        type = "SharedLib"
+        compiler = "MSVC"
+        language = "C++17"
+        platforms = ["x64", "Win32"]
+        output_name = "myplugin"
+        lto = false
+
+        [[projects]]
+        name = "libs/net"
+        type = "StaticLib"
+        compiler = "MSVC"
+        language = "C++17"
+        dependencies = { core = "*", zlib = "1.3" }
+    "#;
+
+    fn workspace() -> Vec<Project> {
+        toml::from_str::<ProjectsFile>(MANIFEST).unwrap().projects
+    }
+
+    fn generated(name: &str) -> String {
+        let workspace = workspace();
+        let project = workspace.iter().find(|p| p.name == name).unwrap();
+        let vs_projects = workspace.iter().collect::<Vec<_>>();
+        vcxproj(
+            project,
+            &workspace,
+            &vs_projects,
+            &Compiler::assumed(project),
+            &[],
+        )
+    }
+
+    fn elements<'a>(document: &'a Document, tag: &str) -> Vec<Node<'a, 'a>> {
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name(tag))
+            .collect()
+    }
+
+    fn texts(document: &Document, tag: &str) -> Vec<String> {
+        elements(document, tag)
+            .iter()
+            .map(|node| node.text().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn configuration_type_follows_project_type() {
+        for (name, expected) in [
+            ("app", "Application"),
+            ("gui", "Application"),
+            ("core", "StaticLibrary"),
+            ("plugin", "DynamicLibrary"),
+        ] {
+            let xml = generated(name);
+            let document = Document::parse(&xml).unwrap();
+            let types = texts(&document, "ConfigurationType");
+            assert!(!types.is_empty());
+            assert!(types.iter().all(|t| t == expected), "{}: {:?}", name, types);
+        }
+    }
+
+    #[test]
+    fn each_configuration_has_its_own_directories() {
+        let xml = generated("app");
+        let document = Document::parse(&xml).unwrap();
+        let groups = elements(&document, "PropertyGroup")
+            .into_iter()
+            .filter(|group| group.children().any(|child| child.has_tag_name("OutDir")))
+            .collect::<Vec<_>>();
+        // Debug and Release for x64 and Win32
+        assert_eq!(groups.len(), 4);
+        assert!(groups
+            .iter()
+            .all(|group| group.attribute("Condition").is_some()));
+
+        let mut out_dirs = texts(&document, "OutDir");
+        let mut int_dirs = texts(&document, "IntDir");
+        out_dirs.sort();
+        out_dirs.dedup();
+        int_dirs.sort();
+        int_dirs.dedup();
+        assert_eq!(out_dirs.len(), 4);
+        assert_eq!(int_dirs.len(), 4);
+        assert!(out_dirs.iter().all(|dir| !int_dirs.contains(dir)));
+        assert!(out_dirs
+            .iter()
+            .all(|dir| dir.starts_with("$(MSBuildProjectDirectory)")));
+        assert!(elements(&document, "TargetPath").is_empty());
+    }
+
+    #[test]
+    fn target_extension_follows_project_type() {
+        for (name, expected) in [
+            ("app", ".exe"),
+            ("gui", ".exe"),
+            ("core", ".lib"),
+            ("plugin", ".dll"),
+        ] {
+            let xml = generated(name);
+            let document = Document::parse(&xml).unwrap();
+            let extensions = texts(&document, "TargetExt");
+            assert!(!extensions.is_empty());
+            assert!(
+                extensions.iter().all(|e| e == expected),
+                "{}: {:?}",
+                name,
+                extensions
+            );
+        }
+        let xml = generated("plugin");
+        let document = Document::parse(&xml).unwrap();
+        assert!(texts(&document, "TargetName")
+            .iter()
+            .all(|n| n == "myplugin"));
+    }
+
+    #[test]
+    fn shared_libraries_have_an_import_library() {
+        let xml = generated("plugin");
+        let document = Document::parse(&xml).unwrap();
+        let import_libraries = texts(&document, "ImportLibrary");
+        assert_eq!(import_libraries.len(), 4);
+        assert!(import_libraries
+            .iter()
+            .all(|lib| lib == "$(OutDir)$(TargetName).lib"));
+        assert!(elements(&document, "SubSystem").is_empty());
+
+        for name in ["app", "core"] {
+            let xml = generated(name);
+            let document = Document::parse(&xml).unwrap();
+            assert!(elements(&document, "ImportLibrary").is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn nested_projects_reach_the_workspace_root() {
+        let xml = generated("libs/net");
+        let document = Document::parse(&xml).unwrap();
+        let library_dirs = texts(&document, "AdditionalLibraryDirectories");
+        assert!(!library_dirs.is_empty());
+        assert!(
+            library_dirs
+                .iter()
+                .all(|dirs| dirs.starts_with("..\\..\\deps\\zlib\\lib;")),
+            "{:?}",
+            library_dirs
+        );
+        let include_dirs = texts(&document, "AdditionalIncludeDirectories");
+        assert!(!include_dirs.is_empty());
+        assert!(
+            include_dirs.iter().all(|dirs| dirs
+                .starts_with("include;..\\..\\core\\include;..\\..\\deps\\zlib\\include;")),
+            "{:?}",
+            include_dirs
+        );
+        assert!(texts(&document, "TargetName")
+            .iter()
+            .all(|name| name == "net"));
+        let references = elements(&document, "ProjectReference")
+            .iter()
+            .filter_map(|node| node.attribute("Include"))
+            .collect::<Vec<_>>();
+        assert_eq!(references, ["..\\..\\core\\core.vcxproj"]);
+    }
+
+    #[test]
+    fn whole_program_optimization_defaults_to_release() {
+        for (name, release) in [("app", "true"), ("plugin", "false")] {
+            let xml = generated(name);
+            let document = Document::parse(&xml).unwrap();
+            let groups = elements(&document, "WholeProgramOptimization");
+            assert_eq!(groups.len(), 4, "{}", name);
+            for node in groups {
+                let condition = node.parent().unwrap().attribute("Condition").unwrap();
+                let expected = if condition.contains("'Release|") {
+                    release
+                } else {
+                    "false"
+                };
+                assert_eq!(node.text(), Some(expected), "{}: {}", name, condition);
+            }
+        }
+    }
+
+    #[test]
+    fn guids_are_upper_case_and_independent_of_the_checkout() {
+        let xml = generated("libs/net");
+        let document = Document::parse(&xml).unwrap();
+        let guid = texts(&document, "ProjectGuid").remove(0);
+        // Derived from the manifest alone, so it is the same in any directory
+        assert_eq!(guid, "{977E9086-544A-5445-ADE4-05B97DCC92A4}");
+        assert_eq!(solution_path(&workspace()), PathBuf::from("app.sln"));
+
+        let projects = workspace();
+        let solution = solution(&projects.iter().collect::<Vec<_>>(), "17");
+        assert!(solution.contains(&guid), "{}", solution);
+        for braced in solution.split('{').skip(1) {
+            let guid = braced.split('}').next().unwrap();
+            assert_eq!(guid, guid.to_uppercase(), "{}", solution);
+        }
+    }
+
+    #[test]
+    fn subsystem_follows_project_type() {
+        for (name, expected) in [
+            ("app", Some("Console")),
+            ("gui", Some("Windows")),
+            ("core", None),
+        ] {
+            let xml = generated(name);
+            let document = Document::parse(&xml).unwrap();
+            let subsystems = texts(&document, "SubSystem");
+            match expected {
+                Some(expected) => {
+                    assert_eq!(subsystems.len(), 4, "{}", name);
+                    assert!(
+                        subsystems.iter().all(|s| s == expected),
+                        "{}: {:?}",
+                        name,
+                        subsystems
+                    );
+                }
+                None => assert!(subsystems.is_empty(), "{}: {:?}", name, subsystems),
+            }
+        }
     }
 }
@@ -0,0 +1,96 @@
+// src/compilation_database.rs
+use crate::build_systems::native::sources;
+use crate::build_systems::BuildSystem;
+use crate::compiler::Compiler;
+use crate::project::Project;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// Written to the workspace root, where clangd and clang-tidy look for it
+const COMPILATION_DATABASE: &str = "compile_commands.json";
+
+/// One translation unit in the clang JSON compilation database format.
+#[derive(Serialize, Deserialize)]
+pub struct CompileCommand {
+    directory: String,
+    arguments: Vec<String>,
+    file: String,
+    output: String,
+}
+
+impl CompileCommand {
+    /// An entry run in `directory`, with `file` and `output` relative to it.
+    /// A relative `directory` is taken from the workspace root, an empty one
+    /// is the root itself.
+    pub fn new(directory: &Path, arguments: Vec<String>, file: &str, output: &str) -> Self {
+        let directory = match std::env::current_dir() {
+            Ok(workspace_dir) if directory.as_os_str().is_empty() => workspace_dir,
+            Ok(workspace_dir) => workspace_dir.join(directory),
+            Err(_) => directory.to_path_buf(),
+        };
+        CompileCommand {
+            directory: directory.to_string_lossy().to_string(),
+            arguments,
+            file: file.to_string(),
+            output: output.to_string(),
+        }
+    }
+}
+
+/// The commands `build_system` runs for each of the project's sources in
+/// `configuration`.
+pub fn compile_commands(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+    configuration: &str,
+    build_system: &dyn BuildSystem,
+) -> Vec<CompileCommand> {
+    sources(project, Path::new(&project.name))
+        .iter()
+        .map(|source| {
+            build_system.compile_command(project, projects, compiler, configuration, source)
+        })
+        .collect()
+}
+
+/// Writes `compile_commands.json`, replacing the entries of `projects` and
+/// keeping any others already in the file. Like the generated headers it is
+/// only rewritten when the content changes.
+pub fn write_compilation_database(
+    commands: Vec<CompileCommand>,
+    projects: &[Project],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let workspace_dir = std::env::current_dir()?;
+    let path = Path::new(COMPILATION_DATABASE);
+    let regenerated = projects
+        .iter()
+        .map(|p| workspace_dir.join(&p.name))
+        .collect::<Vec<_>>();
+    let existing = fs::read_to_string(path).ok();
+    let mut database = existing
+        .as_deref()
+        .and_then(|content| serde_json::from_str::<Vec<CompileCommand>>(content).ok())
+        .unwrap_or_default();
+    // Backends run the compiler in the project directory or the workspace
+    // root, so entries are matched on where their file is
+    database.retain(|entry| {
+        let file = Path::new(&entry.directory).join(&entry.file);
+        !regenerated.iter().any(|dir| file.starts_with(dir))
+    });
+    database.extend(commands);
+
+    let content = serde_json::to_string_pretty(&database)?;
+    if existing.as_deref() == Some(content.as_str()) {
+        return Ok(());
+    }
+    fs::write(path, content)?;
+    info!(
+        "Wrote {} with {} entries",
+        COMPILATION_DATABASE,
+        database.len()
+    );
+    Ok(())
+}
@@ -1,33 +1,686 @@
 // src/compiler.rs
+use crate::project::{Optimize, Project, Warnings};
+use crate::target::Target;
+use log::{info, warn};
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Spellings to try for each manifest language, newest first; older compilers
+// only know the provisional names
+const STANDARD_SPELLINGS: [(&str, &[&str]); 10] = [
+    ("C89", &["c89", "c90"]),
+    ("C99", &["c99"]),
+    ("C11", &["c11", "c1x"]),
+    ("C17", &["c17", "c18"]),
+    ("C++98", &["c++98"]),
+    ("C++11", &["c++11", "c++0x"]),
+    ("C++14", &["c++14", "c++1y"]),
+    ("C++17", &["c++17", "c++1z"]),
+    ("C++20", &["c++20", "c++2a"]),
+    ("C++23", &["c++23", "c++2b"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompilerFamily {
+    Gcc,
+    Clang,
+    Msvc,
+    Other, // Anything else that accepts the cc command line
+}
+
+/// A compiler found on this machine, with what running it told us.
+#[derive(Debug, Clone)]
 pub struct Compiler {
-    pub name: String,
+    pub family: CompilerFamily,
     pub command: String,
-    pub flags: String,
+    pub version: String,
+    pub target: String,
+    // (manifest language, flag value) for each standard the compiler accepted
+    pub standards: Vec<(String, String)>,
+    pub archiver: String,
+    // Select the target and sysroot and carry the toolchain's own flags
+    pub target_compile_flags: Vec<String>,
+    pub target_link_flags: Vec<String>,
 }
 
 impl Compiler {
-    pub fn new(name: &str) -> Self {
-        match name {
-            "gcc" | "G++" => Compiler {
-                name: "GCC".to_string(),
-                command: "g++".to_string(),
-                flags: "-Wall -O2".to_string(),
-            },
-            "clang" | "Clang" => Compiler {
-                name: "Clang".to_string(),
-                command: "clang++".to_string(),
-                flags: "-Wall -O2".to_string(),
-            },
-            "MSVC" => Compiler {
-                name: "MSVC".to_string(),
-                command: "cl".to_string(),
-                flags: "/EHsc".to_string(),
-            },
-            _ => Compiler {
-                name: "GCC".to_string(),
-                command: "g++".to_string(),
-                flags: "-Wall -O2".to_string(),
+    /// Finds the project's compiler: the target's toolchain entry, then `CC` or
+    /// `CXX`, then the driver named by `compiler` (`g++` rather than `gcc` for
+    /// C++ and so on) on PATH, prefixed with the triple when cross-compiling
+    /// with gcc. If the manifest's driver isn't on PATH it tries gcc, clang and
+    /// cc in that order and warns which one it used. Fails if none can be run,
+    /// it builds for another architecture or doesn't support `language`.
+    pub fn detect(project: &Project) -> Result<Self, Box<dyn std::error::Error>> {
+        let is_cpp = project.language.starts_with("C++");
+        let env_name = if is_cpp { "CXX" } else { "CC" };
+        let toolchain = project.target.as_ref().map(|t| &t.toolchain);
+        let configured = toolchain.and_then(|t| if is_cpp { t.cxx.clone() } else { t.cc.clone() });
+        let from_env = env::var(env_name).ok().filter(|v| !v.is_empty());
+        let driver = driver_name(&project.compiler, is_cpp);
+        if project.target.is_some() && family_from_name(&driver) == CompilerFamily::Msvc {
+            return Err(format!(
+                "{}: MSVC selects its target through `platforms`, not --target",
+                project.name
+            )
+            .into());
+        }
+        let requested = match (&configured, &from_env, &project.target) {
+            (Some(configured), _, _) => configured.clone(),
+            (None, Some(from_env), _) => from_env.clone(),
+            // Cross gcc installs prefixed drivers; clang takes --target instead
+            (None, None, Some(target)) if family_from_name(&driver) != CompilerFamily::Clang => {
+                format!("{}-{}", target.triple, driver)
+            }
+            _ => driver,
+        };
+        let (requested, command) = match find_executable(&requested) {
+            Some(command) => (requested, command),
+            None => match (&configured, &from_env) {
+                (Some(_), _) => {
+                    return Err(format!(
+                        "{}: toolchain compiler {} was not found",
+                        project.name, requested
+                    )
+                    .into())
+                }
+                (None, Some(_)) => {
+                    return Err(format!("{}: {} is not an executable", env_name, requested).into())
+                }
+                // Only the manifest's choice falls back to another compiler
+                _ => {
+                    let alternatives =
+                        fallback_drivers(&requested, is_cpp, project.target.as_ref());
+                    let found = alternatives
+                        .iter()
+                        .find_map(|name| Some((name.clone(), find_executable(name)?)));
+                    match found {
+                        Some((name, command)) => {
+                            warn!(
+                                "{}: compiler '{}' was not found on PATH, using {}",
+                                project.name, requested, name
+                            );
+                            (name, command)
+                        }
+                        None => {
+                            return Err(format!(
+                                "{}: compiler '{}' was not found on PATH (also tried {})",
+                                project.name,
+                                requested,
+                                alternatives.join(", ")
+                            )
+                            .into())
+                        }
+                    }
+                }
             },
+        };
+        let command = command.to_string_lossy().to_string();
+
+        let mut target_flags = Vec::new();
+        if let Some(target) = &project.target {
+            if family_from_name(&requested) == CompilerFamily::Clang {
+                target_flags.push(format!("--target={}", target.triple));
+            }
+            if let Some(sysroot) = &target.toolchain.sysroot {
+                target_flags.push(format!("--sysroot={}", sysroot));
+            }
+        }
+        let mut compiler = if family_from_name(&requested) == CompilerFamily::Msvc {
+            probe_msvc(&command)?
+        } else {
+            let mut probe_flags = target_flags.clone();
+            probe_flags.extend(
+                toolchain
+                    .map(|t| t.compiler_flags.clone())
+                    .unwrap_or_default(),
+            );
+            probe_cc(&command, is_cpp, &probe_flags)?
+        };
+        if let Some(target) = &project.target {
+            if Target::architecture(&compiler.target) != Target::architecture(&target.triple) {
+                return Err(format!(
+                    "{}: {} builds for {}, not {}",
+                    project.name, requested, compiler.target, target.triple
+                )
+                .into());
+            }
+        }
+        if compiler.standard(&project.language).is_none() {
+            let supported = compiler
+                .standards
+                .iter()
+                .map(|(l, _)| l.as_str())
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "{}: {} {} does not support {} (supported: {})",
+                project.name,
+                requested,
+                compiler.version,
+                project.language,
+                if supported.is_empty() {
+                    "none detected".to_string()
+                } else {
+                    supported.join(", ")
+                }
+            )
+            .into());
+        }
+
+        compiler.archiver = toolchain
+            .and_then(|t| t.ar.clone())
+            .or_else(|| env::var("AR").ok().filter(|v| !v.is_empty()))
+            .or_else(|| {
+                let target = project.target.as_ref()?;
+                let prefixed = format!("{}-ar", target.triple);
+                find_executable(&prefixed).map(|_| prefixed)
+            })
+            .unwrap_or(compiler.archiver);
+        compiler.target_compile_flags = target_flags.clone();
+        compiler.target_link_flags = target_flags;
+        if let Some(toolchain) = toolchain {
+            compiler
+                .target_compile_flags
+                .extend(toolchain.compiler_flags.iter().cloned());
+            compiler
+                .target_link_flags
+                .extend(toolchain.linker_flags.iter().cloned());
+        }
+        info!(
+            "Using {} {} ({:?}, {}) for {}",
+            command, compiler.version, compiler.family, compiler.target, project.name
+        );
+        Ok(compiler)
+    }
+
+    /// The compiler the manifest names, unverified. Only for generators whose
+    /// build tool locates the compiler itself.
+    pub fn assumed(project: &Project) -> Self {
+        let is_cpp = project.language.starts_with("C++");
+        let family = family_from_name(&project.compiler);
+        let standards = STANDARD_SPELLINGS
+            .iter()
+            .map(|(language, spellings)| (language.to_string(), spellings[0].to_string()))
+            .collect();
+        Compiler {
+            family,
+            command: driver_name(&project.compiler, is_cpp),
+            version: "unknown".to_string(),
+            target: "unknown".to_string(),
+            standards,
+            archiver: if family == CompilerFamily::Msvc {
+                "lib"
+            } else {
+                "ar"
+            }
+            .to_string(),
+            target_compile_flags: Vec::new(),
+            target_link_flags: Vec::new(),
+        }
+    }
+
+    pub fn is_msvc(&self) -> bool {
+        self.family == CompilerFamily::Msvc
+    }
+
+    /// The value to pass for `language`, e.g. `c++2a` for C++20 on an older gcc.
+    /// MSVC has no switch for C89 or C99, so those are the empty string.
+    pub fn standard(&self, language: &str) -> Option<&str> {
+        self.standards
+            .iter()
+            .find(|(l, _)| l == language)
+            .map(|(_, flag)| flag.as_str())
+    }
+
+    /// The key for this family in per-toolchain `compiler_flags` tables.
+    pub fn family_key(&self) -> &'static str {
+        match self.family {
+            CompilerFamily::Gcc => "gcc",
+            CompilerFamily::Clang => "clang",
+            CompilerFamily::Msvc => "msvc",
+            CompilerFamily::Other => "other",
+        }
+    }
+
+    /// Everything passed when compiling for `configuration`: the standard, the
+    /// translated portable options, then the project's raw flags.
+    pub fn compile_flags(&self, project: &Project, configuration: &str) -> Vec<String> {
+        let options = project.options_for(configuration);
+        let mut flags = self
+            .standard_flag(&project.language)
+            .into_iter()
+            .collect::<Vec<_>>();
+        flags.extend(self.target_compile_flags.iter().cloned());
+        let mut push = |flag: &str| flags.push(flag.to_string());
+        if self.is_msvc() {
+            push("/nologo");
+            match options.warnings {
+                Warnings::None => push("/W0"),
+                Warnings::Default => {}
+                Warnings::All => push("/W3"),
+                Warnings::Extra => push("/W4"),
+            }
+            if options.warnings_as_errors {
+                push("/WX");
+            }
+            push(self.optimize_flag(options.optimize));
+            if options.debug_info {
+                push("/Zi");
+            }
+            if options.is_cpp {
+                push(if options.exceptions {
+                    "/EHsc"
+                } else {
+                    "/EHs-c-"
+                });
+                if !options.rtti {
+                    push("/GR-");
+                }
+            }
+            if options.lto {
+                push("/GL");
+            }
+        } else {
+            match options.warnings {
+                Warnings::None => push("-w"),
+                Warnings::Default => {}
+                Warnings::All => push("-Wall"),
+                Warnings::Extra => {
+                    push("-Wall");
+                    push("-Wextra");
+                }
+            }
+            if options.warnings_as_errors {
+                push("-Werror");
+            }
+            push(self.optimize_flag(options.optimize));
+            if options.debug_info {
+                push("-g");
+            }
+            if options.is_cpp {
+                if !options.exceptions {
+                    push("-fno-exceptions");
+                }
+                if !options.rtti {
+                    push("-fno-rtti");
+                }
+            }
+            if options.pic {
+                push("-fPIC");
+            }
+            if options.lto {
+                push("-flto");
+            }
+        }
+        flags.extend(
+            project
+                .compiler_flags
+                .for_family(self.family_key())
+                .into_iter()
+                .map(String::from),
+        );
+        flags
+    }
+
+    /// Extra arguments for the link step of `configuration`, including the
+    /// project's raw linker flags.
+    pub fn link_flags(&self, project: &Project, configuration: &str) -> Vec<String> {
+        let options = project.options_for(configuration);
+        let mut flags = self.target_link_flags.clone();
+        if self.is_msvc() {
+            if options.debug_info {
+                flags.push("/DEBUG".to_string());
+            }
+            if options.lto {
+                flags.push("/LTCG".to_string());
+            }
+        } else if options.lto {
+            // The optimizer runs again at link time and needs the same level
+            flags.push("-flto".to_string());
+            flags.push(self.optimize_flag(options.optimize).to_string());
+        }
+        // GUI applications start without a console on Windows; elsewhere they are ordinary executables
+        if project.project_type == "Windowed" {
+            if self.is_msvc() {
+                flags.push("/SUBSYSTEM:WINDOWS".to_string());
+            } else if self.target.contains("windows") || self.target.contains("mingw") {
+                flags.push("-mwindows".to_string());
+            }
+        }
+        flags.extend(
+            project
+                .linker_flags
+                .for_family(self.family_key())
+                .into_iter()
+                .map(String::from),
+        );
+        flags
+    }
+
+    fn optimize_flag(&self, optimize: Optimize) -> &'static str {
+        match (self.is_msvc(), optimize) {
+            (true, Optimize::None) => "/Od",
+            (true, Optimize::Speed) => "/O2",
+            (true, Optimize::Size) => "/O1",
+            (false, Optimize::None) => "-O0",
+            (false, Optimize::Speed) => "-O2",
+            (false, Optimize::Size) => "-Os",
+        }
+    }
+
+    /// The same options as `ClCompile` item metadata for MSBuild.
+    pub fn msbuild_compile_properties(
+        &self,
+        project: &Project,
+        configuration: &str,
+    ) -> Vec<(&'static str, String)> {
+        let options = project.options_for(configuration);
+        let mut properties = vec![
+            (
+                "WarningLevel",
+                match options.warnings {
+                    Warnings::None => "TurnOffAllWarnings",
+                    Warnings::Default => "Level1",
+                    Warnings::All => "Level3",
+                    Warnings::Extra => "Level4",
+                }
+                .to_string(),
+            ),
+            (
+                "TreatWarningAsError",
+                options.warnings_as_errors.to_string(),
+            ),
+            (
+                "Optimization",
+                match options.optimize {
+                    Optimize::None => "Disabled",
+                    Optimize::Speed => "MaxSpeed",
+                    Optimize::Size => "MinSpace",
+                }
+                .to_string(),
+            ),
+            (
+                "DebugInformationFormat",
+                if options.debug_info {
+                    "ProgramDatabase"
+                } else {
+                    "None"
+                }
+                .to_string(),
+            ),
+        ];
+        if options.is_cpp {
+            properties.push((
+                "ExceptionHandling",
+                if options.exceptions { "Sync" } else { "false" }.to_string(),
+            ));
+            properties.push(("RuntimeTypeInfo", options.rtti.to_string()));
+        }
+        properties
+    }
+
+    /// The complete switch for `language`, if one is needed.
+    pub fn standard_flag(&self, language: &str) -> Option<String> {
+        match self.standard(language)? {
+            "" => None,
+            standard if self.is_msvc() => Some(format!("/std:{}", standard)),
+            standard => Some(format!("-std={}", standard)),
         }
     }
 }
+
+fn family_from_name(name: &str) -> CompilerFamily {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match stem.as_str() {
+        "msvc" | "cl" => CompilerFamily::Msvc,
+        s if s.starts_with("clang") => CompilerFamily::Clang,
+        s if s.starts_with("gcc") || s.starts_with("g++") => CompilerFamily::Gcc,
+        _ => CompilerFamily::Other,
+    }
+}
+
+/// Maps the manifest's `compiler` to the driver for the language.
+fn driver_name(compiler: &str, is_cpp: bool) -> String {
+    match compiler.to_ascii_lowercase().as_str() {
+        "msvc" | "cl" => "cl".to_string(),
+        "gcc" | "g++" => if is_cpp { "g++" } else { "gcc" }.to_string(),
+        "clang" | "clang++" => if is_cpp { "clang++" } else { "clang" }.to_string(),
+        _ => compiler.to_string(),
+    }
+}
+
+/// The cc-compatible drivers to try, in order, when `requested` isn't on
+/// PATH. MSVC has no stand-in; cross builds try the prefixed gcc and clang.
+fn fallback_drivers(requested: &str, is_cpp: bool, target: Option<&Target>) -> Vec<String> {
+    if family_from_name(requested) == CompilerFamily::Msvc {
+        return Vec::new();
+    }
+    let (gcc, clang, cc) = if is_cpp {
+        ("g++", "clang++", "c++")
+    } else {
+        ("gcc", "clang", "cc")
+    };
+    let family = match target {
+        Some(target) => vec![format!("{}-{}", target.triple, gcc), clang.to_string()],
+        None => vec![gcc.to_string(), clang.to_string(), cc.to_string()],
+    };
+    family
+        .into_iter()
+        .filter(|name| name != requested)
+        .collect()
+}
+
+/// Resolves `name` against PATH (adding `.exe` on Windows), or checks it
+/// directly when it is already a path.
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let candidates = |path: PathBuf| {
+        let mut paths = vec![path.clone()];
+        if cfg!(windows) && path.extension().is_none() {
+            paths.push(path.with_extension("exe"));
+        }
+        paths
+    };
+    if name.contains('/') || name.contains('\\') {
+        return candidates(PathBuf::from(name))
+            .into_iter()
+            .find(|p| p.is_file());
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| candidates(dir.join(name)))
+        .find(|p| p.is_file())
+}
+
+fn output_of(command: &str, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new(command)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    Ok(format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+/// gcc, clang and compatible drivers: `--version` names the family,
+/// `-dumpmachine` gives the target, and each `-std=` is tried on an empty
+/// translation unit.
+fn probe_cc(
+    command: &str,
+    is_cpp: bool,
+    flags: &[String],
+) -> Result<Compiler, Box<dyn std::error::Error>> {
+    let banner = output_of(command, &["--version"])?;
+    let first_line = banner.lines().next().unwrap_or_default();
+    let family = if banner.contains("clang") {
+        CompilerFamily::Clang
+    } else if is_gcc_banner(first_line) && banner.contains("Free Software Foundation") {
+        CompilerFamily::Gcc
+    } else {
+        CompilerFamily::Other
+    };
+    let version = match family {
+        // gcc's banner carries the distribution's package version as well
+        CompilerFamily::Gcc => output_of(command, &["-dumpfullversion", "-dumpversion"])?
+            .trim()
+            .to_string(),
+        _ => version_in(first_line).unwrap_or_else(|| "unknown".to_string()),
+    };
+    let mut dumpmachine = flags.iter().map(String::as_str).collect::<Vec<_>>();
+    dumpmachine.push("-dumpmachine");
+    let target = output_of(command, &dumpmachine)
+        .ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty() && !t.contains(' '))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let mut standards = Vec::new();
+    for (language, spellings) in STANDARD_SPELLINGS {
+        if language.starts_with("C++") != is_cpp {
+            continue;
+        }
+        if let Some(spelling) = spellings
+            .iter()
+            .find(|s| accepts_standard(command, is_cpp, flags, s))
+        {
+            standards.push((language.to_string(), spelling.to_string()));
+        }
+    }
+    Ok(Compiler {
+        family,
+        command: command.to_string(),
+        version,
+        target,
+        standards,
+        archiver: "ar".to_string(),
+        target_compile_flags: Vec::new(),
+        target_link_flags: Vec::new(),
+    })
+}
+
+// e.g. `gcc (Debian 12.2.0-14) 12.2.0`, `x86_64-w64-mingw32-g++ (GCC) 13.1.0` or `cc (GCC) 11.4.0`
+fn is_gcc_banner(first_line: &str) -> bool {
+    let program = first_line.split_whitespace().next().unwrap_or_default();
+    program.contains("gcc") || program.contains("g++") || program == "cc" || program == "c++"
+}
+
+fn accepts_standard(command: &str, is_cpp: bool, flags: &[String], standard: &str) -> bool {
+    let child = Command::new(command)
+        .args(flags)
+        .arg(format!("-std={}", standard))
+        .args([
+            "-fsyntax-only",
+            "-Werror",
+            "-x",
+            if is_cpp { "c++" } else { "c" },
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(b"int cpm_probe;\n");
+    }
+    child.wait().map(|s| s.success()).unwrap_or(false)
+}
+
+/// `cl` prints its banner, e.g. `Microsoft (R) C/C++ Optimizing Compiler
+/// Version 19.38.33130 for x64`, when run without arguments. Standard support
+/// follows from the version since `cl` ignores switches it doesn't know.
+fn probe_msvc(command: &str) -> Result<Compiler, Box<dyn std::error::Error>> {
+    let banner = output_of(command, &[])?;
+    let first_line = banner.lines().next().unwrap_or_default();
+    let version =
+        version_in(first_line).ok_or_else(|| format!("{} is not a Microsoft compiler", command))?;
+    let target = match first_line.rsplit(' ').next().unwrap_or_default() {
+        "x64" => "x86_64-pc-windows-msvc",
+        "x86" | "80x86" => "i686-pc-windows-msvc",
+        "ARM64" => "aarch64-pc-windows-msvc",
+        "ARM" => "thumbv7a-pc-windows-msvc",
+        _ => "unknown-pc-windows-msvc",
+    }
+    .to_string();
+
+    let numeric = version
+        .split('.')
+        .take(2)
+        .map(|p| p.parse::<u32>().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let at_least = |minor: u32| {
+        numeric.first() > Some(&19)
+            || (numeric.first() == Some(&19) && numeric.get(1) >= Some(&minor))
+    };
+    let mut standards = vec![
+        ("C89".to_string(), String::new()),
+        ("C99".to_string(), String::new()),
+    ];
+    let table: [(&str, &str, u32); 7] = [
+        ("C11", "c11", 28),
+        ("C17", "c17", 28),
+        ("C++14", "c++14", 0),
+        ("C++17", "c++17", 11),
+        ("C++20", "c++20", 29),
+        ("C++23", "c++latest", 0),
+        ("C++11", "", 0), // The default before /std existed; there's no switch for it
+    ];
+    for (language, flag, minor) in table {
+        if at_least(minor) {
+            standards.push((language.to_string(), flag.to_string()));
+        }
+    }
+    Ok(Compiler {
+        family: CompilerFamily::Msvc,
+        command: command.to_string(),
+        version,
+        target,
+        standards,
+        archiver: "lib".to_string(),
+        target_compile_flags: Vec::new(),
+        target_link_flags: Vec::new(),
+    })
+}
+
+/// The first dotted number in `line`, e.g. `14.0.6` from `clang version 14.0.6`.
+fn version_in(line: &str) -> Option<String> {
+    line.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_digit()))
+        .find(|word| {
+            word.contains('.')
+                && word
+                    .split('.')
+                    .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_drivers_fall_back_through_the_family_list() {
+        assert_eq!(fallback_drivers("gcc", false, None), ["clang", "cc"]);
+        assert_eq!(fallback_drivers("clang++", true, None), ["g++", "c++"]);
+        assert_eq!(fallback_drivers("tcc", false, None), ["gcc", "clang", "cc"]);
+        assert!(fallback_drivers("cl", true, None).is_empty());
+        let target = Target {
+            triple: "aarch64-linux-gnu".to_string(),
+            toolchain: toml::from_str("").unwrap(),
+        };
+        assert_eq!(
+            fallback_drivers("aarch64-linux-gnu-gcc", false, Some(&target)),
+            ["clang"]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// src/config.rs
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_REGISTRY: &str = "default";
+const DEFAULT_REGISTRY_URL: &str = "http://example.com/packages";
+
+/// User-level settings read from `config.toml` in the cpm home directory.
+#[derive(Debug, Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryConfig>,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub toolchains: HashMap<String, ToolchainConfig>,
+    // Build system name to generator executable, for plugins not named cpm-gen-<name> on PATH
+    #[serde(default)]
+    pub generators: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistryConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HttpConfig {
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64, // seconds
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64, // Seconds without receiving data, not for the whole transfer
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    // Overrides the HTTP_PROXY/HTTPS_PROXY/NO_PROXY environment variables
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            retries: default_retries(),
+            proxy: None,
+        }
+    }
+}
+
+/// A cross toolchain, selected with `--target <name>` or `--target <triple>`.
+/// Unset tools are looked up as `<triple>-gcc` and so on.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ToolchainConfig {
+    #[serde(default)]
+    pub target: Option<String>, // Triple; defaults to the toolchain name
+    #[serde(default)]
+    pub cc: Option<String>,
+    #[serde(default)]
+    pub cxx: Option<String>,
+    #[serde(default)]
+    pub ar: Option<String>,
+    #[serde(default)]
+    pub sysroot: Option<String>,
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    #[serde(default)]
+    pub linker_flags: Vec<String>,
+}
+
+/// Secrets read from `credentials.toml`, kept apart from `config.toml` so the
+/// latter can be shared.
+#[derive(Debug, Deserialize, Default)]
+pub struct Credentials {
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryCredentials>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistryCredentials {
+    pub token: String,
+}
+
+/// A registry with its URL and token resolved.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    pub name: String,
+    pub url: String,
+    pub token: Option<String>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = cpm_home().join("config.toml");
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn registry(&self, name: Option<&str>) -> Result<Registry, Box<dyn std::error::Error>> {
+        let name = name.unwrap_or(DEFAULT_REGISTRY);
+        let url = match self.registries.get(name) {
+            Some(registry) => registry.url.trim_end_matches('/').to_string(),
+            None if name == DEFAULT_REGISTRY => DEFAULT_REGISTRY_URL.to_string(),
+            None => return Err(format!("Registry '{}' is not defined in config.toml", name).into()),
+        };
+        let credentials = Credentials::load()?;
+        let token = credentials.registries.get(name).map(|c| c.token.clone());
+        Ok(Registry {
+            name: name.to_string(),
+            url,
+            token,
+        })
+    }
+}
+
+impl Credentials {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = cpm_home().join("credentials.toml");
+        if !path.exists() {
+            return Ok(Credentials::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+}
+
+/// `$CPM_HOME`, falling back to `~/.cpm`.
+pub fn cpm_home() -> PathBuf {
+    if let Some(home) = env::var_os("CPM_HOME") {
+        return PathBuf::from(home);
+    }
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(".").to_path_buf());
+    home.join(".cpm")
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    60
+}
+
+fn default_retries() -> u32 {
+    3
+}
@@ -0,0 +1,335 @@
+// src/config_header.rs
+use crate::build_systems::native::include_dirs;
+use crate::compiler::Compiler;
+use crate::project::{ConfigHeader, Project};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Tried in order for `sizeof` checks; anything else is reported as unknown
+const SIZEOF_CANDIDATES: [usize; 7] = [1, 2, 4, 8, 12, 16, 32];
+
+/// Writes the project's `[projects.config_header]`, running any checks not
+/// already in the probe cache. The file is left untouched when its content
+/// hasn't changed so it doesn't trigger rebuilds.
+pub fn write_config_header(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config_header, header_path) = match (&project.config_header, project.config_header_path())
+    {
+        (Some(config_header), Some(path)) => (config_header, path),
+        _ => return Ok(()),
+    };
+    let project_dir = Path::new(&project.name);
+    let work_dir = project_dir.join(project.output_dir()).join(".cpm");
+    fs::create_dir_all(&work_dir)?;
+
+    let mut prober = Prober::new(project, projects, compiler, &work_dir)?;
+    let content = render(project, config_header, &mut prober)?;
+    prober.save_cache()?;
+
+    let header_path = project_dir.join(header_path);
+    if fs::read_to_string(&header_path).ok().as_deref() == Some(content.as_str()) {
+        return Ok(());
+    }
+    if let Some(parent) = header_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&header_path, content)?;
+    info!("Wrote {}", header_path.display());
+    Ok(())
+}
+
+fn render(
+    project: &Project,
+    config_header: &ConfigHeader,
+    prober: &mut Prober,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let name = macro_name(&project.name);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/* Generated by cpm from [projects.config_header]. Do not edit. */"
+    )?;
+    writeln!(out, "#ifndef {}_CONFIG_H", name)?;
+    writeln!(out, "#define {}_CONFIG_H\n", name)?;
+
+    if let Some(version) = &project.version {
+        writeln!(out, "#define {}_VERSION \"{}\"\n", name, version)?;
+    }
+
+    if !project.preprocessor_definitions.is_empty() {
+        for definition in &project.preprocessor_definitions {
+            match definition.split_once('=') {
+                Some((key, value)) => writeln!(out, "#define {} {}", key, value)?,
+                None => writeln!(out, "#define {} 1", definition)?,
+            }
+        }
+        writeln!(out)?;
+    }
+
+    for header in &config_header.has_header {
+        let code = format!("#include <{}>\n", header);
+        let found = prober.check(&format!("has_header:{}", header), &code, false)?;
+        write_result(&mut out, &format!("HAVE_{}", macro_name(header)), found)?;
+    }
+    for function in &config_header.has_function {
+        // Declared with a dummy prototype and linked, as autoconf does
+        let code = format!(
+            "#ifdef __cplusplus\nextern \"C\"\n#endif\nchar {0}(void);\nint main(void) {{ return {0}() != 0; }}\n",
+            function
+        );
+        let found = prober.check(&format!("has_function:{}", function), &code, true)?;
+        write_result(&mut out, &format!("HAVE_{}", macro_name(function)), found)?;
+    }
+    for check in &config_header.has_symbol {
+        let code = format!(
+            "#include <{}>\nint main(void) {{\n#ifndef {1}\n    (void){1};\n#endif\n    return 0;\n}}\n",
+            check.header, check.symbol
+        );
+        let key = format!("has_symbol:{}:{}", check.header, check.symbol);
+        let found = prober.check(&key, &code, false)?;
+        write_result(
+            &mut out,
+            &format!("HAVE_{}", macro_name(&check.symbol)),
+            found,
+        )?;
+    }
+    for type_name in &config_header.sizeof {
+        let macro_suffix = macro_name(&type_name.replace('*', "p"));
+        match prober.size_of(type_name)? {
+            Some(size) => writeln!(out, "#define SIZEOF_{} {}", macro_suffix, size)?,
+            None => writeln!(out, "/* #undef SIZEOF_{} */", macro_suffix)?,
+        }
+    }
+    for check in &config_header.compiles {
+        let key = format!(
+            "compiles:{}:{}",
+            check.name,
+            hex(&Sha256::digest(check.code.as_bytes())[..8])
+        );
+        let found = prober.check(&key, &check.code, false)?;
+        write_result(&mut out, &check.name, found)?;
+    }
+
+    writeln!(out, "\n#endif")?;
+    Ok(out)
+}
+
+fn write_result(out: &mut String, name: &str, found: bool) -> std::fmt::Result {
+    if found {
+        writeln!(out, "#define {} 1", name)
+    } else {
+        writeln!(out, "/* #undef {} */", name)
+    }
+}
+
+/// Compiles probe snippets with the project's compiler. Results are cached in
+/// `probes-<hash>.toml`, keyed by a hash of the compiler, its version and
+/// target, the language standard and the project's flags, definitions and
+/// include directories, so changing any of them reprobes.
+struct Prober<'a> {
+    project: &'a Project,
+    compiler: &'a Compiler,
+    work_dir: &'a Path,
+    // `-D` and `-I` as the project's sources see them, so its own and its
+    // packages' headers can be checked
+    preprocessor_flags: Vec<String>,
+    cache_path: PathBuf,
+    cache: BTreeMap<String, String>,
+    dirty: bool,
+}
+
+impl<'a> Prober<'a> {
+    fn new(
+        project: &'a Project,
+        projects: &[Project],
+        compiler: &'a Compiler,
+        work_dir: &'a Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let preprocessor_flags = preprocessor_flags(project, projects, compiler)?;
+        let mut hasher = Sha256::new();
+        hasher.update(compiler.command.as_bytes());
+        hasher.update(compiler.version.as_bytes());
+        hasher.update(compiler.target.as_bytes());
+        hasher.update(
+            compiler
+                .standard_flag(&project.language)
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        let raw_flags = project.compiler_flags.for_family(compiler.family_key());
+        for flag in compiler
+            .target_compile_flags
+            .iter()
+            .map(String::as_str)
+            .chain(raw_flags)
+            .chain(preprocessor_flags.iter().map(String::as_str))
+        {
+            hasher.update(flag.as_bytes());
+        }
+        let hash = hex(&hasher.finalize()[..8]);
+
+        let cache_path = work_dir.join(format!("probes-{}.toml", hash));
+        let cache = match fs::read_to_string(&cache_path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Prober {
+            project,
+            compiler,
+            work_dir,
+            preprocessor_flags,
+            cache_path,
+            cache,
+            dirty: false,
+        })
+    }
+
+    fn check(
+        &mut self,
+        key: &str,
+        code: &str,
+        link: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some(result) = self.cache.get(key) {
+            return Ok(result == "1");
+        }
+        let found = self.compile(code, link)?;
+        info!("Checking {}... {}", key, if found { "yes" } else { "no" });
+        self.cache
+            .insert(key.to_string(), if found { "1" } else { "0" }.to_string());
+        self.dirty = true;
+        Ok(found)
+    }
+
+    /// Finds the size at compile time, so it works when cross-compiling too.
+    fn size_of(&mut self, type_name: &str) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        let key = format!("sizeof:{}", type_name);
+        if let Some(result) = self.cache.get(&key) {
+            return Ok(result.parse().ok());
+        }
+        let mut size = None;
+        for candidate in SIZEOF_CANDIDATES {
+            let code = format!(
+                "#include <stddef.h>\n#include <stdint.h>\ntypedef char cpm_probe[(sizeof({}) == {}) ? 1 : -1];\n",
+                type_name, candidate
+            );
+            if self.compile(&code, false)? {
+                size = Some(candidate);
+                break;
+            }
+        }
+        info!(
+            "Checking sizeof({})... {}",
+            type_name,
+            size.map_or("unknown".to_string(), |s| s.to_string())
+        );
+        self.cache
+            .insert(key, size.map_or("?".to_string(), |s| s.to_string()));
+        self.dirty = true;
+        Ok(size)
+    }
+
+    fn compile(&self, code: &str, link: bool) -> Result<bool, Box<dyn std::error::Error>> {
+        let extension = if self.project.language.starts_with("C++") {
+            "cpp"
+        } else {
+            "c"
+        };
+        let source = self.work_dir.join(format!("probe.{}", extension));
+        fs::write(&source, code)?;
+
+        let mut command = Command::new(&self.compiler.command);
+        command
+            .current_dir(self.work_dir)
+            .args(self.compiler.standard_flag(&self.project.language))
+            .args(&self.compiler.target_compile_flags)
+            .args(
+                self.project
+                    .compiler_flags
+                    .for_family(self.compiler.family_key()),
+            )
+            .args(&self.preprocessor_flags);
+        if link {
+            command.args(&self.compiler.target_link_flags);
+        }
+        if self.compiler.is_msvc() {
+            command.arg("/nologo");
+            if link {
+                command.arg("/Feprobe.exe");
+            } else {
+                command.args(["/c", "/Foprobe.obj"]);
+            }
+        } else if link {
+            command.args(["-o", "probe.out"]);
+        } else {
+            command.args(["-c", "-o", "probe.o"]);
+        }
+        command.arg(source.file_name().expect("probe has a file name"));
+        let status = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("Failed to run {}: {}", self.compiler.command, e))?;
+        Ok(status.success())
+    }
+
+    fn save_cache(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.dirty {
+            fs::write(&self.cache_path, toml::to_string(&self.cache)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The project's definitions and include directories as compiler flags. The
+/// directories are made absolute since probes are compiled in the work
+/// directory, not the project's.
+fn preprocessor_flags(
+    project: &Project,
+    projects: &[Project],
+    compiler: &Compiler,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let (include, define) = if compiler.is_msvc() {
+        ("/I", "/D")
+    } else {
+        ("-I", "-D")
+    };
+    let project_dir = std::env::current_dir()?.join(&project.name);
+    let mut flags = project
+        .preprocessor_definitions
+        .iter()
+        .map(|d| format!("{}{}", define, d))
+        .collect::<Vec<_>>();
+    flags.extend(
+        include_dirs(project, projects)
+            .iter()
+            .map(|dir| format!("{}{}", include, project_dir.join(dir).display())),
+    );
+    Ok(flags)
+}
+
+/// Upper-cases `name` and replaces anything that can't appear in a macro with `_`.
+pub fn macro_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -2,7 +2,7 @@
 use crate::project::Project;
 use std::collections::{HashMap, HashSet};
 
-pub fn resolve_dependencies<'a>(projects: &'a [Project]) -> Vec<&'a Project> {
+pub fn resolve_dependencies(projects: &[Project]) -> Vec<&Project> {
     let mut build_order = Vec::new();
     let mut visited = HashSet::new();
     let project_map = projects
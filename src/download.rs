@@ -0,0 +1,322 @@
+// src/download.rs
+use crate::config::HttpConfig;
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Proxy, StatusCode};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// Archives smaller than this finish too quickly for a progress line to be useful
+const PROGRESS_THRESHOLD: u64 = 1024 * 1024;
+const MAX_BACKOFF_SECS: u64 = 16;
+
+pub struct Downloader {
+    client: Client,
+    retries: u32,
+}
+
+enum DownloadError {
+    // Worth retrying: timeouts, dropped connections, 408/429/5xx responses
+    Transient(String),
+    Fatal(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Transient(msg) | DownloadError::Fatal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Fatal(e.to_string())
+    }
+}
+
+impl Downloader {
+    /// Builds a client from the `[http]` config. Unless `proxy` is set there,
+    /// reqwest picks up HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY.
+    pub fn new(http: &HttpConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        // The blocking client applies `timeout` to each wait separately: for the
+        // response headers and for every read of the body. A slow download keeps
+        // going as long as data arrives; only a stalled one is cut off and retried.
+        let mut builder = Client::builder()
+            .user_agent(concat!("cpm/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(Duration::from_secs(http.connect_timeout))
+            .timeout(Duration::from_secs(http.read_timeout));
+        if let Some(proxy) = &http.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        Ok(Downloader {
+            client: builder.build()?,
+            retries: http.retries,
+        })
+    }
+
+    /// Downloads `url` to `destination`, resuming from `<destination>.part` if
+    /// an earlier attempt was interrupted.
+    pub fn download(
+        &self,
+        url: &str,
+        destination: &Path,
+        token: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let partial = partial_path(destination);
+        let mut attempt = 0;
+        loop {
+            match self.try_download(url, &partial, token) {
+                Ok(()) => {
+                    fs::rename(&partial, destination)?;
+                    return Ok(());
+                }
+                Err(DownloadError::Transient(msg)) if attempt < self.retries => {
+                    attempt += 1;
+                    let delay = backoff(attempt);
+                    warn!(
+                        "{} (retrying in {}s, attempt {}/{})",
+                        msg,
+                        delay.as_secs(),
+                        attempt,
+                        self.retries
+                    );
+                    thread::sleep(delay);
+                }
+                Err(e) => return Err(format!("Failed to download {}: {}", url, e).into()),
+            }
+        }
+    }
+
+    /// Fetches a small document such as a registry index into memory.
+    pub fn fetch_text(
+        &self,
+        url: &str,
+        token: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url);
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            let result = request
+                .send()
+                .map_err(|e| classify_request_error(url, e))
+                .and_then(|mut response| {
+                    check_status(url, response.status())?;
+                    // Read rather than `text()`, which would bound the whole body by the timeout
+                    let mut text = String::new();
+                    response.read_to_string(&mut text).map_err(|e| {
+                        DownloadError::Transient(format!(
+                            "Connection to {} interrupted: {}",
+                            url, e
+                        ))
+                    })?;
+                    Ok(text)
+                });
+            match result {
+                Ok(text) => return Ok(text),
+                Err(DownloadError::Transient(msg)) if attempt < self.retries => {
+                    attempt += 1;
+                    let delay = backoff(attempt);
+                    warn!("{} (retrying in {}s)", msg, delay.as_secs());
+                    thread::sleep(delay);
+                }
+                Err(e) => return Err(e.to_string().into()),
+            }
+        }
+    }
+
+    fn try_download(
+        &self,
+        url: &str,
+        partial: &Path,
+        token: Option<&str>,
+    ) -> Result<(), DownloadError> {
+        let resume_from = fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.client.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if resume_from > 0 {
+            request = request.header(RANGE, format!("bytes={}-", resume_from));
+        }
+        let mut response = request.send().map_err(|e| classify_request_error(url, e))?;
+
+        let status = response.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && resume_from > 0 {
+            // The server reports the full length as "bytes */<len>"; if we already
+            // have that many bytes the previous attempt only missed the rename.
+            let complete_length = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit('/').next())
+                .and_then(|len| len.parse::<u64>().ok());
+            if complete_length == Some(resume_from) {
+                return Ok(());
+            }
+            fs::remove_file(partial)?;
+            return Err(DownloadError::Transient(format!(
+                "{} rejected the resume range, restarting",
+                url
+            )));
+        }
+        check_status(url, status)?;
+
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"));
+        if is_html {
+            return Err(DownloadError::Fatal(format!(
+                "{} returned an HTML page instead of a package archive",
+                url
+            )));
+        }
+
+        let (mut file, offset) = if status == StatusCode::PARTIAL_CONTENT {
+            (OpenOptions::new().append(true).open(partial)?, resume_from)
+        } else {
+            // The server ignored the Range header, start over
+            (File::create(partial)?, 0)
+        };
+
+        let total = response.content_length().map(|len| len + offset);
+        let mut progress = Progress::new(url, offset, total);
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let read = response.read(&mut buffer).map_err(|e| {
+                DownloadError::Transient(format!("Connection to {} interrupted: {}", url, e))
+            })?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            progress.advance(read as u64);
+        }
+        file.flush()?;
+        progress.finish();
+
+        if let Some(total) = total {
+            let written = fs::metadata(partial)?.len();
+            if written < total {
+                return Err(DownloadError::Transient(format!(
+                    "{} ended after {} of {} bytes",
+                    url, written, total
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_status(url: &str, status: StatusCode) -> Result<(), DownloadError> {
+    if status.is_success() {
+        Ok(())
+    } else if status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+    {
+        Err(DownloadError::Transient(format!(
+            "{} returned HTTP {}",
+            url, status
+        )))
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        Err(DownloadError::Fatal(format!(
+            "{} returned HTTP {}; check the registry token in credentials.toml",
+            url, status
+        )))
+    } else {
+        Err(DownloadError::Fatal(format!(
+            "{} returned HTTP {}",
+            url, status
+        )))
+    }
+}
+
+fn classify_request_error(url: &str, e: reqwest::Error) -> DownloadError {
+    if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() {
+        DownloadError::Transient(format!("Request to {} failed: {}", url, e))
+    } else {
+        DownloadError::Fatal(format!("Request to {} failed: {}", url, e))
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs((1u64 << attempt.min(4)).min(MAX_BACKOFF_SECS))
+}
+
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+struct Progress {
+    label: String,
+    done: u64,
+    total: Option<u64>,
+    visible: bool,
+    last_percent: u64,
+}
+
+impl Progress {
+    fn new(url: &str, done: u64, total: Option<u64>) -> Self {
+        let label = url.rsplit('/').next().unwrap_or(url).to_string();
+        let visible = total.is_some_and(|t| t >= PROGRESS_THRESHOLD);
+        if visible && done > 0 {
+            info!("Resuming {} at {} bytes", label, done);
+        }
+        Progress {
+            label,
+            done,
+            total,
+            visible,
+            last_percent: u64::MAX,
+        }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        let total = match self.total {
+            Some(total) if self.visible && total > 0 => total,
+            _ => return,
+        };
+        let percent = self.done * 100 / total;
+        if percent == self.last_percent {
+            return;
+        }
+        self.last_percent = percent;
+        let mut stderr = std::io::stderr();
+        if stderr.is_terminal() {
+            let _ = write!(
+                stderr,
+                "\r  {} {:>3}% ({:.1}/{:.1} MiB)",
+                self.label,
+                percent,
+                self.done as f64 / 1048576.0,
+                total as f64 / 1048576.0
+            );
+        } else if percent.is_multiple_of(25) {
+            info!("{}: {}%", self.label, percent);
+        }
+    }
+
+    fn finish(&self) {
+        if self.visible && std::io::stderr().is_terminal() {
+            eprintln!();
+        }
+    }
+}
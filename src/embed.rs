@@ -0,0 +1,185 @@
+// src/embed.rs
+use crate::build_systems::shell_join;
+use crate::path_lookup::cpm_program;
+use crate::project::{CustomCommand, Project, GENERATED_DIR};
+use log::warn;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const BYTES_PER_LINE: usize = 12;
+
+/// Adds the commands for each project's `[[projects.embed]]` entries to its
+/// custom commands. Done before generating rather than when parsing, since
+/// only building needs the files to exist.
+pub fn add_embed_commands(projects: &mut [Project]) -> Result<(), Box<dyn std::error::Error>> {
+    for project in projects.iter_mut() {
+        let commands = embed_commands(project)?;
+        project.custom_commands.extend(commands);
+    }
+    Ok(())
+}
+
+/// Turns each `[[projects.embed]]` entry into a custom command that runs
+/// `cpm embed`, so every generator schedules it like any other code generator.
+/// Globs are expanded now, relative to the project directory.
+fn embed_commands(project: &Project) -> Result<Vec<CustomCommand>, Box<dyn std::error::Error>> {
+    if project.embed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let project_dir = Path::new(&project.name);
+    let cpm = cpm_program();
+    let extension = if project.language.starts_with("C++") {
+        "cpp"
+    } else {
+        "c"
+    };
+
+    let mut commands = Vec::new();
+    for embed in &project.embed {
+        let mut files = Vec::new();
+        for pattern in &embed.files {
+            let full_pattern = project_dir.join(pattern).to_string_lossy().to_string();
+            let mut matched = glob::glob(&full_pattern)?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .map(|path| {
+                    path.strip_prefix(project_dir)
+                        .unwrap_or(&path)
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect::<Vec<_>>();
+            if matched.is_empty() {
+                warn!(
+                    "{}: embed pattern '{}' matches no files",
+                    project.name, pattern
+                );
+            }
+            matched.sort();
+            files.extend(matched);
+        }
+        if files.is_empty() {
+            warn!(
+                "{}: nothing to embed into {}; skipping it",
+                project.name, embed.name
+            );
+            continue;
+        }
+
+        let header = format!("{}/{}.h", GENERATED_DIR, embed.name);
+        let source = format!("{}/{}.{}", GENERATED_DIR, embed.name, extension);
+        let mut arguments = vec![
+            cpm.clone(),
+            "embed".to_string(),
+            "--prefix".to_string(),
+            embed.prefix.clone(),
+            "--header".to_string(),
+            header.clone(),
+            "--source".to_string(),
+            source.clone(),
+        ];
+        arguments.extend(files.iter().cloned());
+        commands.push(CustomCommand {
+            command: shell_join(&arguments),
+            description: Some(format!(
+                "Embedding {} file(s) into {}",
+                files.len(),
+                embed.name
+            )),
+            inputs: files,
+            outputs: vec![header, source],
+        });
+    }
+    Ok(commands)
+}
+
+/// `cpm embed`: writes `header` declaring `<prefix><file>[]` and
+/// `<prefix><file>_size` for each file, and `source` defining them. The arrays
+/// carry a trailing NUL not counted in the size so text can be used directly.
+pub fn write_embedded_sources(
+    files: &[String],
+    prefix: &str,
+    header_path: &Path,
+    source_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let guard = header_path
+        .file_name()
+        .map(|n| sanitize(&n.to_string_lossy()).to_ascii_uppercase())
+        .unwrap_or_else(|| "EMBEDDED_H".to_string());
+    let header_name = header_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut header = String::new();
+    writeln!(header, "/* Generated by cpm embed. Do not edit. */")?;
+    writeln!(header, "#ifndef {}", guard)?;
+    writeln!(header, "#define {}\n", guard)?;
+    writeln!(header, "#include <stddef.h>\n")?;
+    writeln!(header, "#ifdef __cplusplus\nextern \"C\" {{\n#endif\n")?;
+
+    let mut source = String::new();
+    writeln!(source, "/* Generated by cpm embed. Do not edit. */")?;
+    writeln!(source, "#include \"{}\"\n", header_name)?;
+
+    let mut symbols = HashSet::new();
+    for file in files {
+        let path = Path::new(file);
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| file.clone());
+        let symbol = format!("{}{}", prefix, sanitize(&file_name));
+        if !symbols.insert(symbol.clone()) {
+            return Err(format!(
+                "Two embedded files map to the symbol {}; rename one of them",
+                symbol
+            )
+            .into());
+        }
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", file, e))?;
+
+        writeln!(header, "/* {} */", file)?;
+        writeln!(header, "extern const unsigned char {}[];", symbol)?;
+        writeln!(header, "extern const size_t {}_size;\n", symbol)?;
+
+        writeln!(source, "/* {} */", file)?;
+        writeln!(source, "const unsigned char {}[] = {{", symbol)?;
+        for chunk in bytes.chunks(BYTES_PER_LINE) {
+            let line = chunk
+                .iter()
+                .map(|b| format!("0x{:02x},", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(source, "    {}", line)?;
+        }
+        writeln!(source, "    0x00")?;
+        writeln!(source, "}};")?;
+        writeln!(source, "const size_t {}_size = {};\n", symbol, bytes.len())?;
+    }
+
+    writeln!(header, "#ifdef __cplusplus\n}}\n#endif\n")?;
+    writeln!(header, "#endif")?;
+
+    for path in [header_path, source_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(header_path, header)?;
+    fs::write(source_path, source)?;
+    Ok(())
+}
+
+fn sanitize(name: &str) -> String {
+    let mut symbol = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+        symbol.insert(0, '_');
+    }
+    symbol
+}
@@ -0,0 +1,52 @@
+// src/external_command.rs
+use crate::build_systems::plugin::PLUGIN_PREFIX;
+use crate::path_lookup::{find_on_path, programs_with_prefix};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+const COMMAND_PREFIX: &str = "cpm-";
+
+/// `cpm-<name>` executables on PATH by name, leaving out generator plugins
+/// and names that `builtin` commands already take.
+pub fn external_commands(builtin: &[&str]) -> BTreeMap<String, PathBuf> {
+    let mut commands = programs_with_prefix(COMMAND_PREFIX);
+    commands.retain(|name, _| {
+        !builtin.contains(&name.as_str())
+            && !format!("{}{}", COMMAND_PREFIX, name).starts_with(PLUGIN_PREFIX)
+    });
+    commands
+}
+
+/// Runs `cpm-<name>` with `args` from the current directory, returning its
+/// exit code. Like cpm itself, it takes the current directory as the
+/// workspace root.
+pub fn run_external_command(
+    name: &str,
+    args: &[OsString],
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let program = format!("{}{}", COMMAND_PREFIX, name);
+    let executable = find_on_path(&program)
+        .ok_or_else(|| format!("no such command: `{}` (see cpm --list)", name))?;
+    let workspace_dir = env::current_dir()?;
+    let mut command = Command::new(&executable);
+    command
+        .args(args)
+        .env("CPM_WORKSPACE_DIR", &workspace_dir)
+        .env("CPM_VERSION", env!("CARGO_PKG_VERSION"));
+    // Only a workspace has a manifest; commands like `cpm new` run outside one
+    let manifest_path = workspace_dir.join("project.toml");
+    if manifest_path.is_file() {
+        command.env("CPM_MANIFEST_PATH", manifest_path);
+    }
+    // Lets the command run cpm again without finding it on PATH
+    if let Ok(cpm) = env::current_exe() {
+        command.env("CPM", cpm);
+    }
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", executable.display(), e))?;
+    Ok(status.code().unwrap_or(1))
+}
@@ -0,0 +1,67 @@
+// src/hooks.rs
+use crate::project::Project;
+use std::path::Path;
+use std::process::Command;
+
+/// Variables exposed to pre/post-build commands. `project_dir` and
+/// `configuration` may be build-system expressions such as `$(CURDIR)`, since
+/// each generator resolves them at build time in its own syntax.
+pub fn hook_environment(
+    project: &Project,
+    project_dir: &str,
+    configuration: &str,
+    separator: char,
+) -> Vec<(&'static str, String)> {
+    let join = |dir: &str| {
+        format!(
+            "{}{}{}",
+            project_dir,
+            separator,
+            dir.replace(['/', '\\'], &separator.to_string())
+        )
+    };
+    vec![
+        ("CPM_PROJECT_NAME", project.name.clone()),
+        ("CPM_PROJECT_DIR", project_dir.to_string()),
+        ("CPM_WORKSPACE_DIR", join("..")),
+        ("CPM_SRC_DIR", join(&project.structure.src_dir)),
+        ("CPM_INCLUDE_DIR", join(&project.structure.include_dir)),
+        ("CPM_OUTPUT_DIR", join(&project.output_dir())),
+        ("CPM_CONFIGURATION", configuration.to_string()),
+    ]
+}
+
+/// Runs pre/post-build commands through the shell inside `project_dir`, for
+/// builds that cpm drives itself rather than through a generated file.
+pub fn run_hooks(
+    project: &Project,
+    commands: &[&str],
+    project_dir: &Path,
+    configuration: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = project_dir.to_string_lossy();
+    let environment = hook_environment(project, &dir, configuration, std::path::MAIN_SEPARATOR);
+    for command in commands {
+        println!("{}", command);
+        let status = shell(command)
+            .current_dir(project_dir)
+            .envs(environment.iter().cloned())
+            .status()?;
+        if !status.success() {
+            return Err(format!("`{}` failed with {}", command, status).into());
+        }
+    }
+    Ok(())
+}
+
+/// `command` run by `sh -c`, or `cmd /C` on Windows.
+pub fn shell(command: &str) -> Command {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
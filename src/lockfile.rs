@@ -0,0 +1,121 @@
+// src/lockfile.rs
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const LOCKFILE_NAME: &str = "cpm.lock";
+const LOCKFILE_HEADER: &str = "# This file is generated by cpm. Do not edit it by hand.\n\n";
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Lockfile {
+    #[serde(default, rename = "package", skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub registry: String,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Lockfile::default());
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut sorted = self.clone();
+        sorted.packages.sort_by(|a, b| a.name.cmp(&b.name));
+        let content = toml::to_string(&sorted)?;
+        fs::write(path, format!("{}{}", LOCKFILE_HEADER, content))?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+}
+
+/// The lockfile sits next to the manifest it locks.
+pub fn lockfile_path(manifest_path: &str) -> PathBuf {
+    Path::new(manifest_path).with_file_name(LOCKFILE_NAME)
+}
+
+/// Prints the version changes between two lockfiles, returning whether there were any.
+pub fn print_diff(old: &Lockfile, new: &Lockfile) -> bool {
+    let changes = diff(old, new);
+    for change in &changes {
+        println!("{}", change);
+    }
+    !changes.is_empty()
+}
+
+/// The version changes between two lockfiles, one line per package.
+fn diff(old: &Lockfile, new: &Lockfile) -> Vec<String> {
+    let mut changes = Vec::new();
+    for package in &new.packages {
+        match old.get(&package.name) {
+            Some(previous) if previous.version == package.version => {}
+            Some(previous) => changes.push(format!(
+                "    Updating {} {} -> {}",
+                package.name, previous.version, package.version
+            )),
+            None => changes.push(format!("      Adding {} {}", package.name, package.version)),
+        }
+    }
+    for package in &old.packages {
+        if new.get(&package.name).is_none() {
+            changes.push(format!("    Removing {} {}", package.name, package.version));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile(packages: &[(&str, &str)]) -> Lockfile {
+        Lockfile {
+            packages: packages
+                .iter()
+                .map(|(name, version)| LockedPackage {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    registry: "default".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_reports_updates_additions_and_removals() {
+        let old = lockfile(&[("zlib", "1.2.13"), ("fmt", "10.1.0"), ("json", "3.11.2")]);
+        let new = lockfile(&[("zlib", "1.3.0"), ("fmt", "10.1.0"), ("curl", "8.4.0")]);
+        assert_eq!(
+            diff(&old, &new),
+            [
+                "    Updating zlib 1.2.13 -> 1.3.0",
+                "      Adding curl 8.4.0",
+                "    Removing json 3.11.2",
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn lockfiles_read_back_what_they_wrote() {
+        let written = lockfile(&[("zlib", "1.3.0"), ("curl", "8.4.0")]);
+        let mut sorted = written.clone();
+        sorted.packages.sort_by(|a, b| a.name.cmp(&b.name));
+        let content = toml::to_string(&sorted).unwrap();
+        let read: Lockfile = toml::from_str(&format!("{}{}", LOCKFILE_HEADER, content)).unwrap();
+        assert_eq!(read, sorted);
+        assert_eq!(lockfile_path("app/cpm.toml"), Path::new("app/cpm.lock"));
+    }
+}
@@ -1,29 +1,69 @@
 // src/main.rs
-mod parser;
-mod project;
 mod build_systems;
-mod package_manager;
-mod dependency_resolver;
+mod compilation_database;
 mod compiler;
+mod config;
+mod config_header;
+mod dependency_resolver;
+mod download;
+mod embed;
+mod external_command;
+mod hooks;
+mod lockfile;
+mod manifest_editor;
+mod package_manager;
+mod parser;
+mod path_lookup;
+mod project;
+mod registry_index;
+mod scaffold;
+mod target;
+mod version_header;
 
 use clap::{Arg, Command};
-use env_logger;
 
-use crate::project::Project;
-use crate::parser::parse_project_file;
+use crate::build_systems::{
+    build_project, build_systems, clean_project, generate_build_configs, selected_build_system,
+    BuildRequest, GenerateContext,
+};
+use crate::compilation_database::{compile_commands, write_compilation_database, CompileCommand};
+use crate::compiler::Compiler;
 use crate::dependency_resolver::resolve_dependencies;
-use crate::package_manager::{install_project_dependencies, reinstall_package};
-use crate::build_systems::{generate_build_configs};
+use crate::embed::{add_embed_commands, write_embedded_sources};
+use crate::external_command::{external_commands, run_external_command};
+use crate::lockfile::lockfile_path;
+use crate::package_manager::{
+    add_package, install_workspace_dependencies, print_outdated, reinstall_package, remove_package,
+    update_dependencies,
+};
+use crate::parser::parse_project_file;
+use crate::project::Project;
+use crate::scaffold::{init_project, new_workspace, ScaffoldOptions, LANGUAGES, PROJECT_TYPES};
+use crate::target::Target;
 
-use std::path::PathBuf;
+use std::path::Path;
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    
-    let matches = Command::new("CPM")
-        .version("1.0")
+
+    let cli = Command::new("CPM")
+        .version(env!("CARGO_PKG_VERSION"))
         .author("Chase Sunstrom <casunstrom@gmail.com>")
         .about("Package Manager and Build Tool")
+        // `cpm foo` runs `cpm-foo` from PATH when foo isn't built in
+        .allow_external_subcommands(true)
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .help("Lists built-in commands and cpm-<name> commands found on PATH")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("list_build_systems")
+                .long("list-build-systems")
+                .help("Lists the build systems that can be used in build_systems and with -b")
+                .action(clap::ArgAction::SetTrue),
+        )
         .subcommand(
             Command::new("generate")
                 .about("Generates build configurations")
@@ -33,6 +73,28 @@ fn main() {
                         .help("Path to the project file")
                         .value_name("FILE")
                         .num_args(1),
+                )
+                .arg(
+                    Arg::new("build_system")
+                        .short('b')
+                        .help("Build system to generate instead of the manifest's build_systems")
+                        .value_name("BUILD_SYSTEM")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .help("Configuration for compile_commands.json and single-configuration generators")
+                        .value_name("CONFIG")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Target triple or toolchain name from config.toml to cross-compile for")
+                        .value_name("TARGET")
+                        .num_args(1),
                 ),
         )
         .subcommand(
@@ -51,6 +113,63 @@ fn main() {
                         .help("Build system to use for building")
                         .value_name("BUILD_SYSTEM")
                         .num_args(1),
+                )
+                .arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .help("Configuration to build, e.g. Debug or Release")
+                        .value_name("CONFIG")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Target triple or toolchain name from config.toml to cross-compile for")
+                        .value_name("TARGET")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .help("Number of parallel jobs for downloads and native compiles")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("Removes what the projects' build systems have built")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("build_system")
+                        .short('b')
+                        .help("Build system to clean instead of each project's first")
+                        .value_name("BUILD_SYSTEM")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .help("Configuration to clean, e.g. Debug or Release")
+                        .value_name("CONFIG")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Target triple or toolchain name from config.toml to clean for")
+                        .value_name("TARGET")
+                        .num_args(1),
                 ),
         )
         .subcommand(
@@ -62,11 +181,34 @@ fn main() {
                         .help("Path to the project file")
                         .value_name("FILE")
                         .num_args(1),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Target triple or toolchain name from config.toml to cross-compile for")
+                        .value_name("TARGET")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .help("Number of packages to download in parallel")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
                 ),
         )
         .subcommand(
             Command::new("reinstall")
-                .about("Reinstalls a package with a different version")
+                .about("Pins a package to a different version and reinstalls it")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
                 .arg(
                     Arg::new("package_name")
                         .help("Name of the package to reinstall")
@@ -82,19 +224,282 @@ fn main() {
                         .num_args(1),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("update")
+                .about("Updates locked package versions within the manifest requirements")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("package_name")
+                        .help("Only update this package")
+                        .value_name("PACKAGE")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("outdated")
+                .about("Lists packages with newer versions in the registry")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Adds a dependency to a project")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("project")
+                        .short('p')
+                        .help("Project whose dependencies to change")
+                        .value_name("PROJECT")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("package")
+                        .help("Package to add, optionally with a version requirement")
+                        .required(true)
+                        .value_name("PACKAGE[@REQ]")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .help("Number of packages to download in parallel")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Removes a dependency from a project")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("project")
+                        .short('p')
+                        .help("Project whose dependencies to change")
+                        .value_name("PROJECT")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("package")
+                        .help("Package to remove")
+                        .required(true)
+                        .value_name("PACKAGE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .help("Number of packages to download in parallel")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("new")
+                .about("Creates a new workspace with a starter project")
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the project")
+                        .required(true)
+                        .value_name("NAME")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .help("Kind of project to create")
+                        .value_name("TYPE")
+                        .value_parser(PROJECT_TYPES)
+                        .default_value("Console")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("lang")
+                        .long("lang")
+                        .help("Language standard")
+                        .value_name("LANGUAGE")
+                        .value_parser(LANGUAGES)
+                        .default_value("C++20")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .help("Template directory, or the name of one in ~/.cpm/templates")
+                        .value_name("TEMPLATE")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Adds a starter project to an existing workspace")
+                .arg(
+                    Arg::new("project_file")
+                        .short('f')
+                        .help("Path to the project file")
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the project")
+                        .required(true)
+                        .value_name("NAME")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .help("Kind of project to create")
+                        .value_name("TYPE")
+                        .value_parser(PROJECT_TYPES)
+                        .default_value("Console")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("lang")
+                        .long("lang")
+                        .help("Language standard")
+                        .value_name("LANGUAGE")
+                        .value_parser(LANGUAGES)
+                        .default_value("C++20")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .help("Template directory, or the name of one in ~/.cpm/templates")
+                        .value_name("TEMPLATE")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("embed")
+                .about("Writes a C source and header embedding files (run by generated build files)")
+                .hide(true)
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .help("Prefix for the generated symbols")
+                        .value_name("PREFIX")
+                        .default_value("")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("header")
+                        .long("header")
+                        .help("Header to write")
+                        .required(true)
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .help("Source file to write")
+                        .required(true)
+                        .value_name("FILE")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("files")
+                        .help("Files to embed")
+                        .required(true)
+                        .value_name("FILES")
+                        .num_args(1..),
+                ),
+        );
+    let matches = cli.clone().get_matches();
 
-    if let Some(matches) = matches.subcommand_matches("generate") {
+    if matches.get_flag("list") {
+        println!("Built-in commands:");
+        let builtin = cli
+            .get_subcommands()
+            .map(|c| c.get_name())
+            .collect::<Vec<_>>();
+        // Hidden commands aren't listed, but still keep external ones from shadowing them
+        for command in cli.get_subcommands().filter(|c| !c.is_hide_set()) {
+            println!(
+                "    {:<12} {}",
+                command.get_name(),
+                command
+                    .get_about()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default()
+            );
+        }
+        let external = external_commands(&builtin);
+        if !external.is_empty() {
+            println!("External commands:");
+            for (name, path) in external {
+                println!("    {:<12} {}", name, path.display());
+            }
+        }
+    } else if matches.get_flag("list_build_systems") {
+        for build_system in build_systems() {
+            println!("{:<14} {}", build_system.name(), build_system.description());
+        }
+    } else if let Some(matches) = matches.subcommand_matches("generate") {
         let file_path = matches
             .get_one::<String>("project_file")
             .map_or("project.toml", |s| s.as_str());
         match parse_project_file(file_path) {
-            Ok(projects_file) => {
-                for project in &projects_file.projects {
-                    if let Err(e) = generate_build_configs(project) {
-                        eprintln!("Error generating build configs for {}: {}", project.name, e);
+            Ok(mut projects_file) => {
+                if let Err(e) = apply_target(matches, &mut projects_file.projects) {
+                    eprintln!("Error selecting target: {}", e);
+                    return;
+                }
+                if let Err(e) = add_embed_commands(&mut projects_file.projects) {
+                    eprintln!("Error expanding embedded resources: {}", e);
+                    return;
+                }
+                let configuration = matches.get_one::<String>("config");
+                let context = GenerateContext {
+                    workspace: &projects_file.projects,
+                    manifest_path: file_path,
+                    build_system: matches
+                        .get_one::<String>("build_system")
+                        .map(String::as_str),
+                    configuration: configuration.map(String::as_str),
+                };
+                let mut compile_commands = Vec::new();
+                // Dependencies first, so generators can refer to what they wrote
+                for project in resolve_dependencies(&projects_file.projects) {
+                    match generate_build_configs(project, &context) {
+                        Ok(compiler) => compile_commands
+                            .push(project_compile_commands(project, &context, &compiler)),
+                        Err(e) => {
+                            eprintln!("Error generating build configs for {}: {}", project.name, e)
+                        }
                     }
                 }
+                write_compile_commands(compile_commands, &projects_file.projects);
             }
             Err(e) => eprintln!("Error parsing project file: {}", e),
         }
@@ -102,112 +507,320 @@ fn main() {
         let file_path = matches
             .get_one::<String>("project_file")
             .map_or("project.toml", |s| s.as_str());
-        let build_system = matches.get_one::<String>("build_system");
+        let build_system = matches
+            .get_one::<String>("build_system")
+            .map(String::as_str);
+        let configuration = matches.get_one::<String>("config");
 
         match parse_project_file(file_path) {
-            Ok(projects_file) => {
-                let build_order = resolve_dependencies(&projects_file.projects);
-                for project in build_order {
-                    if let Err(e) = install_project_dependencies(project) {
-                        eprintln!("Error installing dependencies for {}: {}", project.name, e);
-                        continue;
+            Ok(mut projects_file) => {
+                let triple = match apply_target(matches, &mut projects_file.projects) {
+                    Ok(triple) => triple,
+                    Err(e) => {
+                        eprintln!("Error selecting target: {}", e);
+                        return;
                     }
-                    if let Err(e) = generate_build_configs(project) {
-                        eprintln!("Error generating build configs for {}: {}", project.name, e);
-                        continue;
+                };
+                let lock_path = lockfile_path(file_path);
+                if let Err(e) = install_workspace_dependencies(
+                    &projects_file.projects,
+                    &lock_path,
+                    jobs(matches),
+                    triple.as_deref(),
+                ) {
+                    eprintln!("Error installing dependencies: {}", e);
+                    return;
+                }
+                if let Err(e) = add_embed_commands(&mut projects_file.projects) {
+                    eprintln!("Error expanding embedded resources: {}", e);
+                    return;
+                }
+                // Everything is generated first, since a workspace-level file
+                // such as the top-level CMakeLists.txt names every project
+                let context = GenerateContext {
+                    workspace: &projects_file.projects,
+                    manifest_path: file_path,
+                    build_system,
+                    configuration: configuration.map(String::as_str),
+                };
+                let mut generated = Vec::new();
+                let mut compile_commands = Vec::new();
+                for project in resolve_dependencies(&projects_file.projects) {
+                    match generate_build_configs(project, &context) {
+                        Ok(compiler) => {
+                            compile_commands
+                                .push(project_compile_commands(project, &context, &compiler));
+                            generated.push((project, compiler));
+                        }
+                        Err(e) => {
+                            eprintln!("Error generating build configs for {}: {}", project.name, e)
+                        }
                     }
-                    if let Err(e) = build_project_with_system(project, build_system) {
+                }
+                write_compile_commands(compile_commands, &projects_file.projects);
+                for (project, compiler) in &generated {
+                    let build = BuildRequest {
+                        workspace: &projects_file.projects,
+                        manifest_path: file_path,
+                        compiler,
+                        build_system,
+                        configuration: configuration.map(String::as_str),
+                        jobs: jobs(matches),
+                    };
+                    if let Err(e) = build_project(project, &build) {
                         eprintln!("Error building {}: {}", project.name, e);
                     }
                 }
             }
             Err(e) => eprintln!("Error parsing project file: {}", e),
         }
-    } else if let Some(matches) = matches.subcommand_matches("install") {
+    } else if let Some(matches) = matches.subcommand_matches("clean") {
         let file_path = matches
             .get_one::<String>("project_file")
             .map_or("project.toml", |s| s.as_str());
         match parse_project_file(file_path) {
-            Ok(projects_file) => {
+            Ok(mut projects_file) => {
+                if let Err(e) = apply_target(matches, &mut projects_file.projects) {
+                    eprintln!("Error selecting target: {}", e);
+                    return;
+                }
                 for project in &projects_file.projects {
-                    if let Err(e) = install_project_dependencies(project) {
-                        eprintln!("Error installing dependencies for {}: {}", project.name, e);
+                    // Only output names depend on the compiler, so cleaning doesn't need one installed
+                    let compiler =
+                        Compiler::detect(project).unwrap_or_else(|_| Compiler::assumed(project));
+                    let request = BuildRequest {
+                        workspace: &projects_file.projects,
+                        manifest_path: file_path,
+                        compiler: &compiler,
+                        build_system: matches
+                            .get_one::<String>("build_system")
+                            .map(String::as_str),
+                        configuration: matches.get_one::<String>("config").map(String::as_str),
+                        jobs: 1,
+                    };
+                    if let Err(e) = clean_project(project, &request) {
+                        eprintln!("Error cleaning {}: {}", project.name, e);
                     }
                 }
             }
             Err(e) => eprintln!("Error parsing project file: {}", e),
         }
+    } else if let Some(matches) = matches.subcommand_matches("install") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        match parse_project_file(file_path) {
+            Ok(mut projects_file) => {
+                let triple = match apply_target(matches, &mut projects_file.projects) {
+                    Ok(triple) => triple,
+                    Err(e) => {
+                        eprintln!("Error selecting target: {}", e);
+                        return;
+                    }
+                };
+                let lock_path = lockfile_path(file_path);
+                if let Err(e) = install_workspace_dependencies(
+                    &projects_file.projects,
+                    &lock_path,
+                    jobs(matches),
+                    triple.as_deref(),
+                ) {
+                    eprintln!("Error installing dependencies: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Error parsing project file: {}", e),
+        }
     } else if let Some(matches) = matches.subcommand_matches("reinstall") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
         let package_name = matches
             .get_one::<String>("package_name")
             .expect("package_name is required");
         let version = matches
             .get_one::<String>("version")
             .expect("version is required");
-        if let Err(e) = reinstall_package(package_name, version) {
-            eprintln!("Error reinstalling package {}: {}", package_name, e);
+        match parse_project_file(file_path) {
+            Ok(projects_file) => {
+                let lock_path = lockfile_path(file_path);
+                if let Err(e) =
+                    reinstall_package(&projects_file.projects, &lock_path, package_name, version)
+                {
+                    eprintln!("Error reinstalling package {}: {}", package_name, e);
+                }
+            }
+            Err(e) => eprintln!("Error parsing project file: {}", e),
         }
-    } else {
-        println!("No valid subcommand was provided. Use --help for more information.");
-    }
-}
-
-fn build_project_with_system(
-    project: &Project,
-    build_system: Option<&String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let system = build_system
-        .as_deref()
-        .or_else(|| project.build_systems.first())
-        .map(String::as_str)
-        .unwrap_or("Makefile"); // Default to Makefile if not specified
-
-    match system {
-        "Makefile" => {
-            // Run Makefile
-            let status = std::process::Command::new("make")
-                .current_dir(&project.name)
-                .status()?;
-            if !status.success() {
-                return Err(format!("Build failed for {}", project.name).into());
+    } else if let Some(matches) = matches.subcommand_matches("update") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        let package_name = matches.get_one::<String>("package_name");
+        match parse_project_file(file_path) {
+            Ok(projects_file) => {
+                let lock_path = lockfile_path(file_path);
+                if let Err(e) = update_dependencies(
+                    &projects_file.projects,
+                    &lock_path,
+                    package_name.map(String::as_str),
+                ) {
+                    eprintln!("Error updating dependencies: {}", e);
+                }
             }
+            Err(e) => eprintln!("Error parsing project file: {}", e),
         }
-        "VisualStudio" => {
-            // Run msbuild.exe
-            let solution_file = format!("{}/{}.sln", project.name, project.name);
-            let status = std::process::Command::new("msbuild.exe")
-                .arg(&solution_file)
-                .status()?;
-            if !status.success() {
-                return Err(format!("Build failed for {}", project.name).into());
+    } else if let Some(matches) = matches.subcommand_matches("outdated") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        match parse_project_file(file_path) {
+            Ok(projects_file) => {
+                if let Err(e) = print_outdated(&projects_file.projects, &lockfile_path(file_path)) {
+                    eprintln!("Error checking for outdated packages: {}", e);
+                }
             }
+            Err(e) => eprintln!("Error parsing project file: {}", e),
         }
-        _ => {
-            eprintln!("Unsupported build system: {}", system);
+    } else if let Some(matches) = matches.subcommand_matches("add") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        let project = matches.get_one::<String>("project").map(String::as_str);
+        let package = matches
+            .get_one::<String>("package")
+            .expect("package is required");
+        if let Err(e) = add_package(file_path, project, package, jobs(matches)) {
+            eprintln!("Error adding {}: {}", package, e);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("remove") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        let project = matches.get_one::<String>("project").map(String::as_str);
+        let package = matches
+            .get_one::<String>("package")
+            .expect("package is required");
+        if let Err(e) = remove_package(file_path, project, package, jobs(matches)) {
+            eprintln!("Error removing {}: {}", package, e);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("new") {
+        let options = scaffold_options(matches);
+        if let Err(e) = new_workspace(&options) {
+            eprintln!("Error creating {}: {}", options.name, e);
         }
+    } else if let Some(matches) = matches.subcommand_matches("init") {
+        let file_path = matches
+            .get_one::<String>("project_file")
+            .map_or("project.toml", |s| s.as_str());
+        let options = scaffold_options(matches);
+        if let Err(e) = init_project(file_path, &options) {
+            eprintln!("Error adding {}: {}", options.name, e);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("embed") {
+        let files = matches
+            .get_many::<String>("files")
+            .expect("files are required")
+            .cloned()
+            .collect::<Vec<_>>();
+        let prefix = matches
+            .get_one::<String>("prefix")
+            .expect("prefix has a default");
+        let header = matches
+            .get_one::<String>("header")
+            .expect("header is required");
+        let source = matches
+            .get_one::<String>("source")
+            .expect("source is required");
+        if let Err(e) = write_embedded_sources(&files, prefix, Path::new(header), Path::new(source))
+        {
+            eprintln!("Error embedding resources: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Some((name, matches)) = matches.subcommand() {
+        let args = matches
+            .get_many::<std::ffi::OsString>("")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        match run_external_command(name, &args) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        println!("No valid subcommand was provided. Use --help for more information.");
+    }
+}
+
+fn scaffold_options(matches: &clap::ArgMatches) -> ScaffoldOptions<'_> {
+    ScaffoldOptions {
+        name: matches.get_one::<String>("name").expect("name is required"),
+        project_type: matches
+            .get_one::<String>("type")
+            .expect("type has a default"),
+        language: matches
+            .get_one::<String>("lang")
+            .expect("lang has a default"),
+        template: matches.get_one::<String>("template").map(String::as_str),
     }
-    Ok(())
 }
 
+fn jobs(matches: &clap::ArgMatches) -> usize {
+    matches
+        .get_one::<usize>("jobs")
+        .copied()
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4)
+        })
+}
 
-// Function to locate msbuild.exe
-fn find_msbuild_executable() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    // Common installation paths for msbuild.exe
-    let possible_paths = vec![
-        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Community\MSBuild\Current\Bin\MSBuild.exe",
-        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Professional\MSBuild\Current\Bin\MSBuild.exe",
-        r"C:\Program Files (x86)\Microsoft Visual Studio\2019\Enterprise\MSBuild\Current\Bin\MSBuild.exe",
-        r"C:\Program Files\Microsoft Visual Studio\2022\Community\MSBuild\Current\Bin\MSBuild.exe",
-        r"C:\Program Files\Microsoft Visual Studio\2022\Professional\MSBuild\Current\Bin\MSBuild.exe",
-        r"C:\Program Files\Microsoft Visual Studio\2022\Enterprise\MSBuild\Current\Bin\MSBuild.exe",
-    ];
+/// Resolves `--target` onto every project, returning the triple if one was given.
+fn apply_target(
+    matches: &clap::ArgMatches,
+    projects: &mut [Project],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let target = match matches.get_one::<String>("target") {
+        Some(name) => Target::resolve(name)?,
+        None => return Ok(None),
+    };
+    for project in projects.iter_mut() {
+        project.target = Some(target.clone());
+    }
+    Ok(Some(target.triple))
+}
 
-    for path_str in possible_paths {
-        let path = PathBuf::from(path_str);
-        if path.exists() {
-            return Ok(path);
+/// The compile commands of the build system `project` is built with.
+fn project_compile_commands(
+    project: &Project,
+    context: &GenerateContext,
+    compiler: &Compiler,
+) -> Vec<CompileCommand> {
+    let configuration = context
+        .configuration
+        .unwrap_or(project.default_configuration());
+    match selected_build_system(project, context.build_system) {
+        Ok(build_system) => compile_commands(
+            project,
+            context.workspace,
+            compiler,
+            configuration,
+            build_system.as_ref(),
+        ),
+        Err(e) => {
+            eprintln!("Error listing compile commands for {}: {}", project.name, e);
+            Vec::new()
         }
     }
+}
 
-    Err("msbuild.exe not found. Please ensure Visual Studio is installed, or add msbuild.exe to your PATH.".into())
+fn write_compile_commands(compile_commands: Vec<Vec<CompileCommand>>, projects: &[Project]) {
+    let commands = compile_commands.into_iter().flatten().collect();
+    if let Err(e) = write_compilation_database(commands, projects) {
+        eprintln!("Error writing compile_commands.json: {}", e);
+    }
 }
@@ -0,0 +1,207 @@
+// src/manifest_editor.rs
+use crate::project::ProjectsFile;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Edits `project.toml` in place. Unlike `parser`, which goes through serde,
+/// this keeps comments, ordering and whitespace intact.
+pub struct ManifestEditor {
+    path: PathBuf,
+    document: DocumentMut,
+}
+
+impl ManifestEditor {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let document = content
+            .parse::<DocumentMut>()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(ManifestEditor {
+            path: path.to_path_buf(),
+            document,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(&self.path, self.document.to_string())?;
+        Ok(())
+    }
+
+    /// The manifest as it would be saved, parsed into the project model.
+    pub fn projects_file(&self) -> Result<ProjectsFile, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&self.document.to_string())?)
+    }
+
+    /// Appends a `[[projects]]` entry.
+    pub fn add_project(
+        &mut self,
+        name: &str,
+        project: Table,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.project_mut(name).is_ok() {
+            return Err(format!(
+                "{} already has a project named {}",
+                self.path.display(),
+                name
+            )
+            .into());
+        }
+        let projects = self
+            .document
+            .entry("projects")
+            .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| {
+                format!(
+                    "{}: 'projects' is not an array of tables",
+                    self.path.display()
+                )
+            })?;
+        projects.push(project);
+        Ok(())
+    }
+
+    /// Sets `dependencies.<package>` on the named project, returning the previous
+    /// requirement if there was one.
+    pub fn add_dependency(
+        &mut self,
+        project: &str,
+        package: &str,
+        requirement: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let table = self.project_mut(project)?;
+        if !table.contains_key("dependencies") {
+            let mut dependencies = Table::new();
+            dependencies.set_implicit(false);
+            table.insert("dependencies", Item::Table(dependencies));
+        }
+        let previous = match table.get_mut("dependencies") {
+            Some(Item::Table(dependencies)) => {
+                let previous = dependencies
+                    .get(package)
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+                dependencies.insert(package, value(requirement));
+                previous
+            }
+            Some(Item::Value(toml_edit::Value::InlineTable(dependencies))) => {
+                let previous = dependencies
+                    .get(package)
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+                dependencies.insert(package, requirement.into());
+                dependencies.fmt();
+                previous
+            }
+            _ => {
+                return Err(format!(
+                    "{}: 'dependencies' of {} is not a table",
+                    self.path.display(),
+                    project
+                )
+                .into())
+            }
+        };
+        Ok(previous)
+    }
+
+    /// Removes `dependencies.<package>` from the named project, returning whether it was present.
+    pub fn remove_dependency(
+        &mut self,
+        project: &str,
+        package: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let table = self.project_mut(project)?;
+        let removed = match table.get_mut("dependencies") {
+            Some(Item::Table(dependencies)) => dependencies.remove(package).is_some(),
+            Some(Item::Value(toml_edit::Value::InlineTable(dependencies))) => {
+                let removed = dependencies.remove(package).is_some();
+                dependencies.fmt();
+                removed
+            }
+            _ => false,
+        };
+        Ok(removed)
+    }
+
+    fn project_mut(&mut self, name: &str) -> Result<&mut Table, Box<dyn std::error::Error>> {
+        let path = self.path.display().to_string();
+        self.document
+            .get_mut("projects")
+            .and_then(Item::as_array_of_tables_mut)
+            .ok_or_else(|| format!("{} has no [[projects]] entries", path))?
+            .iter_mut()
+            .find(|table| table.get("name").and_then(Item::as_str) == Some(name))
+            .ok_or_else(|| format!("No project named {} in {}", name, path).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(manifest: &str) -> ManifestEditor {
+        ManifestEditor {
+            path: PathBuf::from("project.toml"),
+            document: manifest.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn table_dependencies_keep_the_rest_of_the_file() {
+        let mut editor = editor(
+            "# The app\n[[projects]]\nname = \"app\" # main binary\ntype = \"Console\"\n\
+             compiler = \"gcc\"\nlanguage = \"C++17\"\n\n\
+             [projects.dependencies]\nzlib = \"1.2.13\" # pinned\n",
+        );
+        assert_eq!(
+            editor.add_dependency("app", "zlib", "1.3.0").unwrap(),
+            Some("1.2.13".to_string())
+        );
+        assert_eq!(editor.add_dependency("app", "fmt", "^10").unwrap(), None);
+        assert_eq!(
+            editor.document.to_string(),
+            "# The app\n[[projects]]\nname = \"app\" # main binary\ntype = \"Console\"\n\
+             compiler = \"gcc\"\nlanguage = \"C++17\"\n\n\
+             [projects.dependencies]\nzlib = \"1.3.0\"\nfmt = \"^10\"\n"
+        );
+        assert!(editor.remove_dependency("app", "zlib").unwrap());
+        assert!(!editor.remove_dependency("app", "zlib").unwrap());
+        let projects = editor.projects_file().unwrap();
+        assert_eq!(projects.projects[0].dependencies.len(), 1);
+    }
+
+    #[test]
+    fn inline_dependencies_stay_inline() {
+        let mut editor = editor(
+            "[[projects]]\nname = \"app\"\ntype = \"Console\"\n\
+             compiler = \"gcc\"\nlanguage = \"C++17\"\n\
+             dependencies = { zlib = \"1.2.13\" }\n",
+        );
+        editor.add_dependency("app", "fmt", "^10").unwrap();
+        assert!(editor
+            .document
+            .to_string()
+            .ends_with("dependencies = { zlib = \"1.2.13\", fmt = \"^10\" }\n"));
+        assert!(editor.remove_dependency("app", "zlib").unwrap());
+        assert!(editor
+            .document
+            .to_string()
+            .ends_with("dependencies = { fmt = \"^10\" }\n"));
+        assert!(editor.add_dependency("lib", "fmt", "^10").is_err());
+    }
+
+    #[test]
+    fn dependencies_table_is_created_when_missing() {
+        let mut editor = editor(
+            "[[projects]]\nname = \"app\"\ntype = \"Console\"\n\
+             compiler = \"gcc\"\nlanguage = \"C++17\"\n",
+        );
+        editor.add_dependency("app", "zlib", "1.3.0").unwrap();
+        assert!(editor
+            .document
+            .to_string()
+            .ends_with("\n[projects.dependencies]\nzlib = \"1.3.0\"\n"));
+    }
+}
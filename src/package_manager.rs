@@ -1,47 +1,570 @@
 // src/package_manager.rs
+use crate::config::{Config, Registry};
+use crate::download::Downloader;
+use crate::lockfile::{lockfile_path, print_diff, LockedPackage, Lockfile};
+use crate::manifest_editor::ManifestEditor;
+use crate::parser::parse_project_file;
 use crate::project::Project;
-use log::{info};
-use reqwest::blocking::get;
+use crate::registry_index::{exact_version, parse_requirement, PackageIndex};
+use crate::target::deps_dir;
+use log::{info, warn};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{self, File};
-use std::io::copy;
 use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
 use zip::ZipArchive;
 
-const PACKAGE_REPO_URL: &str = "http://example.com/packages";
+// Written into deps/<package> so unchanged packages aren't downloaded again
+const INSTALLED_MARKER: &str = ".cpm-version";
 
-pub fn install_project_dependencies(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
-    for (dep_name, version) in &project.dependencies {
-        install_package(dep_name, version)?;
+pub enum UpdatePolicy<'a> {
+    Locked,
+    All,
+    Only(&'a str),
+}
+
+struct Requirements<'a> {
+    registry: Registry,
+    reqs: Vec<(VersionReq, &'a str)>, // Requirement and the project declaring it
+}
+
+impl Requirements<'_> {
+    fn version_reqs(&self) -> Vec<VersionReq> {
+        self.reqs.iter().map(|(req, _)| req.clone()).collect()
+    }
+
+    fn describe(&self) -> String {
+        self.reqs
+            .iter()
+            .map(|(req, project)| format!("{} (from {})", req, project))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Gathers each package's version requirements across the workspace.
+/// Dependencies naming another workspace project are skipped; those are
+/// built, not downloaded.
+fn collect_requirements<'a>(
+    projects: &'a [Project],
+    config: &Config,
+) -> Result<BTreeMap<&'a str, Requirements<'a>>, Box<dyn std::error::Error>> {
+    let workspace_projects = projects
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect::<HashSet<_>>();
+    let mut packages: BTreeMap<&str, Requirements> = BTreeMap::new();
+    for project in projects {
+        let registry = config.registry(project.registry.as_deref())?;
+        for (dep_name, requirement) in &project.dependencies {
+            if workspace_projects.contains(dep_name.as_str()) {
+                continue;
+            }
+            let req = parse_requirement(requirement).map_err(|e| {
+                format!(
+                    "{}: invalid version requirement '{}' for {}: {}",
+                    project.name, requirement, dep_name, e
+                )
+            })?;
+            let entry = packages.entry(dep_name).or_insert_with(|| Requirements {
+                registry: registry.clone(),
+                reqs: Vec::new(),
+            });
+            if entry.registry.name != registry.name {
+                return Err(format!(
+                    "{} is requested from both {} and {} registries",
+                    dep_name, entry.registry.name, registry.name
+                )
+                .into());
+            }
+            entry.reqs.push((req, &project.name));
+        }
+    }
+    Ok(packages)
+}
+
+/// Picks a version for every package the workspace needs. Locked versions are
+/// kept while they still satisfy the manifest unless `policy` asks to update them.
+pub fn resolve_workspace(
+    projects: &[Project],
+    lockfile: &Lockfile,
+    policy: &UpdatePolicy,
+    config: &Config,
+    downloader: &Downloader,
+) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let requirements = collect_requirements(projects, config)?;
+    if let UpdatePolicy::Only(name) = policy {
+        if !requirements.contains_key(name) {
+            return Err(format!("{} is not a dependency of any project", name).into());
+        }
+    }
+
+    let mut resolved = Lockfile::default();
+    for (name, requirements) in &requirements {
+        let reqs = requirements.version_reqs();
+        let update = match policy {
+            UpdatePolicy::Locked => false,
+            UpdatePolicy::All => true,
+            UpdatePolicy::Only(only) => only == name,
+        };
+        let locked = lockfile
+            .get(name)
+            .filter(|_| !update)
+            .filter(|locked| locked.registry == requirements.registry.name)
+            .and_then(|locked| Version::parse(&locked.version).ok())
+            .filter(|version| reqs.iter().all(|req| req.matches(version)));
+
+        let version = match locked {
+            Some(version) => version,
+            None => match PackageIndex::fetch(downloader, &requirements.registry, name) {
+                Ok(index) => index.newest_matching(&reqs).cloned().ok_or_else(|| {
+                    format!("No version of {} matches {}", name, requirements.describe())
+                })?,
+                // Registries that only serve archives have no index, but an
+                // exact version can still be downloaded directly
+                Err(e) => match exact_version(&reqs) {
+                    Some(version) => {
+                        warn!("{}; using the exact version {}", e, version);
+                        version
+                    }
+                    None => return Err(e),
+                },
+            },
+        };
+        resolved.packages.push(LockedPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            registry: requirements.registry.name.clone(),
+        });
+    }
+    Ok(resolved)
+}
+
+/// Installs every locked package exactly once, with up to `jobs` downloads
+/// running at a time. Resolves and writes the lockfile first if it is missing
+/// or no longer matches the manifest. With a `target` triple the prebuilt
+/// packages for that target go to `deps/<triple>`.
+pub fn install_workspace_dependencies(
+    projects: &[Project],
+    lock_path: &Path,
+    jobs: usize,
+    target: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    let downloader = Downloader::new(&config.http)?;
+
+    let lockfile = Lockfile::load(lock_path)?;
+    let resolved = resolve_workspace(
+        projects,
+        &lockfile,
+        &UpdatePolicy::Locked,
+        &config,
+        &downloader,
+    )?;
+    if resolved != lockfile {
+        print_diff(&lockfile, &resolved);
+        resolved.save(lock_path)?;
+    }
+
+    let mut queue = Vec::new();
+    for package in &resolved.packages {
+        if installed_version(&package.name, target).as_deref() != Some(package.version.as_str()) {
+            queue.push((package, config.registry(Some(&package.registry))?));
+        }
+    }
+
+    let queue = Mutex::new(queue);
+    let errors = Mutex::new(Vec::new());
+    let workers = jobs.max(1).min(queue.lock().unwrap().len());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let (package, registry) = match queue.lock().unwrap().pop() {
+                    Some(job) => job,
+                    None => break,
+                };
+                if let Err(e) = install_package(
+                    &downloader,
+                    &registry,
+                    &package.name,
+                    &package.version,
+                    target,
+                ) {
+                    errors
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} {}: {}", package.name, package.version, e));
+                }
+            });
+        }
+    });
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        return Err(format!(
+            "Failed to install {} package(s):\n  {}",
+            errors.len(),
+            errors.join("\n  ")
+        )
+        .into());
+    }
+    prune_packages(&resolved, target)
+}
+
+/// Adds `spec` (`name` or `name@requirement`) to a project's dependencies and
+/// installs it. Without a requirement the newest release is used.
+pub fn add_package(
+    manifest_path: &str,
+    project_name: Option<&str>,
+    spec: &str,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let projects_file = parse_project_file(manifest_path)?;
+    let project = select_project(&projects_file.projects, project_name)?;
+    let (package, requirement) = match spec.split_once('@') {
+        Some((package, requirement)) => (package, Some(requirement)),
+        None => (spec, None),
+    };
+
+    let requirement = if projects_file.projects.iter().any(|p| p.name == package) {
+        // Workspace projects are built, not fetched; only the key matters
+        requirement.unwrap_or("*").to_string()
+    } else {
+        let config = Config::load()?;
+        let downloader = Downloader::new(&config.http)?;
+        let registry = config.registry(project.registry.as_deref())?;
+        let index = PackageIndex::fetch(&downloader, &registry, package);
+        match requirement {
+            Some(requirement) => {
+                let req = parse_requirement(requirement)
+                    .map_err(|e| format!("Invalid version requirement '{}': {}", requirement, e))?;
+                match index {
+                    Ok(index) if index.newest_matching(std::slice::from_ref(&req)).is_none() => {
+                        return Err(
+                            format!("No version of {} matches {}", package, requirement).into()
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) if exact_version(&[req]).is_some() => {
+                        warn!("{}; adding the exact version unchecked", e)
+                    }
+                    Err(e) => return Err(e),
+                }
+                requirement.to_string()
+            }
+            // A bare version would be exact, so the newest release is written as a caret requirement
+            None => index?
+                .latest()
+                .map(|version| format!("^{}", version))
+                .ok_or_else(|| format!("{} has no published releases", package))?,
+        }
+    };
+
+    let mut editor = ManifestEditor::open(Path::new(manifest_path))?;
+    let previous = editor.add_dependency(&project.name, package, &requirement)?;
+    let resolved = resolve_edited_manifest(&editor, manifest_path)?;
+    match previous {
+        Some(previous) => println!(
+            "    Changing {} {} -> {} in {}",
+            package, previous, requirement, project.name
+        ),
+        None => println!(
+            "      Adding {} {} to {}",
+            package, requirement, project.name
+        ),
+    }
+    editor.save()?;
+    resolved.save(&lockfile_path(manifest_path))?;
+
+    let projects_file = parse_project_file(manifest_path)?;
+    install_workspace_dependencies(
+        &projects_file.projects,
+        &lockfile_path(manifest_path),
+        jobs,
+        None,
+    )
+}
+
+/// Removes a package from a project's dependencies and drops it from the
+/// lockfile and `deps/` if nothing else uses it.
+pub fn remove_package(
+    manifest_path: &str,
+    project_name: Option<&str>,
+    package: &str,
+    jobs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let projects_file = parse_project_file(manifest_path)?;
+    let project = select_project(&projects_file.projects, project_name)?;
+
+    let mut editor = ManifestEditor::open(Path::new(manifest_path))?;
+    if !editor.remove_dependency(&project.name, package)? {
+        return Err(format!("{} is not a dependency of {}", package, project.name).into());
+    }
+    let resolved = resolve_edited_manifest(&editor, manifest_path)?;
+    println!("    Removing {} from {}", package, project.name);
+    editor.save()?;
+    resolved.save(&lockfile_path(manifest_path))?;
+
+    let projects_file = parse_project_file(manifest_path)?;
+    install_workspace_dependencies(
+        &projects_file.projects,
+        &lockfile_path(manifest_path),
+        jobs,
+        None,
+    )
+}
+
+/// Resolves the edited manifest without writing anything, so a requirement
+/// that can't be satisfied is reported before the manifest is touched. The
+/// caller saves the manifest and then the returned lockfile.
+fn resolve_edited_manifest(
+    editor: &ManifestEditor,
+    manifest_path: &str,
+) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    let downloader = Downloader::new(&config.http)?;
+    let projects_file = editor.projects_file()?;
+    let lock_path = lockfile_path(manifest_path);
+    let lockfile = Lockfile::load(&lock_path)?;
+    let resolved = resolve_workspace(
+        &projects_file.projects,
+        &lockfile,
+        &UpdatePolicy::Locked,
+        &config,
+        &downloader,
+    )?;
+    print_diff(&lockfile, &resolved);
+    Ok(resolved)
+}
+
+fn select_project<'a>(
+    projects: &'a [Project],
+    name: Option<&str>,
+) -> Result<&'a Project, Box<dyn std::error::Error>> {
+    match name {
+        Some(name) => projects
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("No project named {}", name).into()),
+        None if projects.len() == 1 => Ok(&projects[0]),
+        None => Err("The workspace has several projects; choose one with -p".into()),
+    }
+}
+
+/// Re-resolves locked versions within the manifest requirements and rewrites
+/// the lockfile. Installation is left to `install`/`build`.
+pub fn update_dependencies(
+    projects: &[Project],
+    lock_path: &Path,
+    package: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    let downloader = Downloader::new(&config.http)?;
+    let policy = match package {
+        Some(name) => UpdatePolicy::Only(name),
+        None => UpdatePolicy::All,
+    };
+
+    let lockfile = Lockfile::load(lock_path)?;
+    let resolved = resolve_workspace(projects, &lockfile, &policy, &config, &downloader)?;
+    if !print_diff(&lockfile, &resolved) {
+        println!("All packages are up to date");
+    }
+    resolved.save(lock_path)
+}
+
+/// Lists locked packages that have newer versions in their registry.
+pub fn print_outdated(
+    projects: &[Project],
+    lock_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    let downloader = Downloader::new(&config.http)?;
+    let requirements = collect_requirements(projects, &config)?;
+    let lockfile = Lockfile::load(lock_path)?;
+
+    let mut rows = Vec::new();
+    for (name, requirements) in &requirements {
+        let index = match PackageIndex::fetch(&downloader, &requirements.registry, name) {
+            Ok(index) => index,
+            Err(e) if exact_version(&requirements.version_reqs()).is_some() => {
+                warn!("{}; skipping it", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let locked = lockfile
+            .get(name)
+            .map(|p| p.version.clone())
+            .unwrap_or_else(|| "-".to_string());
+        let compatible = index
+            .newest_matching(&requirements.version_reqs())
+            .map(Version::to_string)
+            .unwrap_or_else(|| "-".to_string());
+        let latest = index
+            .latest()
+            .map(Version::to_string)
+            .unwrap_or_else(|| "-".to_string());
+        if locked != compatible || locked != latest {
+            rows.push([name.to_string(), locked, compatible, latest]);
+        }
+    }
+
+    if rows.is_empty() {
+        println!("All packages are up to date");
+        return Ok(());
+    }
+    let header = ["Package", "Locked", "Compatible", "Latest"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
     }
     Ok(())
 }
 
-pub fn install_package(package: &str, version: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let url = format!("{}/{}/{}.zip", PACKAGE_REPO_URL, package, version);
-    let destination = format!("deps/{}_{}.zip", package, version);
-    info!("Downloading {} version {}", package, version);
-    download_package(&url, &destination)?;
+/// Downloads `<registry>/<package>/<version>.zip`, or for a cross target the
+/// prebuilt `<registry>/<package>/<version>/<triple>.zip`, into the target's deps directory.
+pub fn install_package(
+    downloader: &Downloader,
+    registry: &Registry,
+    package: &str,
+    version: &str,
+    target: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = match target {
+        Some(triple) => format!("{}/{}/{}/{}.zip", registry.url, package, version, triple),
+        None => format!("{}/{}/{}.zip", registry.url, package, version),
+    };
+    let deps = deps_dir(target);
+    let destination = deps
+        .join(format!("{}_{}.zip", package, version))
+        .to_string_lossy()
+        .to_string();
+    let package_path = deps.join(package);
+    match target {
+        Some(triple) => info!(
+            "Downloading {} version {} for {} from {}",
+            package, version, triple, registry.name
+        ),
+        None => info!(
+            "Downloading {} version {} from {}",
+            package, version, registry.name
+        ),
+    }
+    downloader.download(&url, Path::new(&destination), registry.token.as_deref())?;
+    if package_path.exists() {
+        fs::remove_dir_all(&package_path)?;
+    }
     info!("Extracting {} version {}", package, version);
-    extract_package(&destination, &format!("deps/{}", package))?;
+    extract_package(&destination, &package_path.to_string_lossy())
+        .map_err(|e| format!("{} is not a valid package archive: {}", destination, e))?;
+    fs::write(package_path.join(INSTALLED_MARKER), version)?;
     Ok(())
 }
 
-pub fn reinstall_package(package: &str, version: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let package_path = format!("deps/{}", package);
-    if Path::new(&package_path).exists() {
-        fs::remove_dir_all(&package_path)?;
+/// Pins `package` to `version` in the lockfile and installs it. The version has
+/// to satisfy the manifest requirements; widen those first to go further.
+pub fn reinstall_package(
+    projects: &[Project],
+    lock_path: &Path,
+    package: &str,
+    version: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    let downloader = Downloader::new(&config.http)?;
+    let requirements = collect_requirements(projects, &config)?;
+    let requirements = requirements
+        .get(package)
+        .ok_or_else(|| format!("{} is not a dependency of any project", package))?;
+
+    let parsed =
+        Version::parse(version).map_err(|e| format!("Invalid version '{}': {}", version, e))?;
+    match PackageIndex::fetch(&downloader, &requirements.registry, package) {
+        Ok(index) if !index.contains(&parsed) => {
+            return Err(format!(
+                "{} {} is not published in registry {}",
+                package, version, requirements.registry.name
+            )
+            .into());
+        }
+        Ok(_) => {}
+        // Without an index the download itself tells whether the version exists
+        Err(e) => warn!("{}; downloading {} {} directly", e, package, version),
     }
-    install_package(package, version)
+    if let Some((req, project)) = requirements
+        .reqs
+        .iter()
+        .find(|(req, _)| !req.matches(&parsed))
+    {
+        return Err(format!(
+            "{} {} does not satisfy requirement {} in {}",
+            package, version, req, project
+        )
+        .into());
+    }
+
+    let lockfile = Lockfile::load(lock_path)?;
+    let mut updated = lockfile.clone();
+    updated.packages.retain(|p| p.name != package);
+    updated.packages.push(LockedPackage {
+        name: package.to_string(),
+        version: parsed.to_string(),
+        registry: requirements.registry.name.clone(),
+    });
+    print_diff(&lockfile, &updated);
+    updated.save(lock_path)?;
+
+    install_package(
+        &downloader,
+        &requirements.registry,
+        package,
+        &parsed.to_string(),
+        None,
+    )
 }
 
-fn download_package(url: &str, destination: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut response = get(url)?;
-    fs::create_dir_all("deps")?;
-    let mut dest = File::create(destination)?;
-    copy(&mut response, &mut dest)?;
+/// Deletes installed packages that the lockfile no longer lists. Per-target
+/// directories have no marker, so pruning the host's packages leaves them alone.
+fn prune_packages(
+    lockfile: &Lockfile,
+    target: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = match fs::read_dir(deps_dir(target)) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if path.join(INSTALLED_MARKER).exists() && lockfile.get(name).is_none() {
+            info!("Removing unused package {}", name);
+            fs::remove_dir_all(&path)?;
+        }
+    }
     Ok(())
 }
 
+/// The version of `package` installed for `target`, if any.
+pub fn installed_version(package: &str, target: Option<&str>) -> Option<String> {
+    fs::read_to_string(deps_dir(target).join(package).join(INSTALLED_MARKER)).ok()
+}
+
 fn extract_package(file_path: &str, extract_to: &str) -> zip::result::ZipResult<()> {
     let file = File::open(file_path)?;
     let mut archive = ZipArchive::new(file)?;
@@ -57,7 +580,7 @@ fn extract_package(file_path: &str, extract_to: &str) -> zip::result::ZipResult<
         } else {
             if let Some(p) = outpath.parent() {
                 if !p.exists() {
-                    fs::create_dir_all(&p)?;
+                    fs::create_dir_all(p)?;
                 }
             }
             let mut outfile = File::create(&outpath)?;
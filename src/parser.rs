@@ -5,5 +5,15 @@ use std::fs;
 pub fn parse_project_file(file_path: &str) -> Result<ProjectsFile, Box<dyn std::error::Error>> {
     let content = fs::read_to_string(file_path)?;
     let projects_file: ProjectsFile = toml::from_str(&content)?;
+    for project in &projects_file.projects {
+        if let Some(version) = &project.version {
+            semver::Version::parse(version).map_err(|e| {
+                format!(
+                    "{}: version '{}' is not a semantic version: {}",
+                    project.name, version, e
+                )
+            })?;
+        }
+    }
     Ok(projects_file)
 }
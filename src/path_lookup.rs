@@ -0,0 +1,71 @@
+// src/path_lookup.rs
+use log::warn;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Finds `program` in the directories on PATH, as the shell would.
+pub fn find_on_path(program: &str) -> Option<PathBuf> {
+    path_dirs().into_iter().find_map(|dir| {
+        let path = dir.join(executable_name(program));
+        is_executable(&path).then_some(path)
+    })
+}
+
+/// How generated build files run cpm: `$CPM` if it is set, else `cpm` from
+/// PATH. A full path to the running executable would break once the build
+/// files are used on another machine.
+pub fn cpm_program() -> String {
+    if let Some(cpm) = env::var_os("CPM") {
+        return cpm.to_string_lossy().to_string();
+    }
+    if find_on_path("cpm").is_none() {
+        warn!("cpm is not on PATH; the generated build files run it, so add it to PATH or set CPM");
+    }
+    "cpm".to_string()
+}
+
+/// Executables on PATH named `<prefix><name>`, keyed by name. Earlier PATH
+/// entries win, as they would when run.
+pub fn programs_with_prefix(prefix: &str) -> BTreeMap<String, PathBuf> {
+    let mut programs = BTreeMap::new();
+    for dir in path_dirs() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let stem = file_name
+                .strip_suffix(env::consts::EXE_SUFFIX)
+                .unwrap_or(&file_name);
+            if let Some(name) = stem.strip_prefix(prefix) {
+                if !name.is_empty() && !programs.contains_key(name) && is_executable(&path) {
+                    programs.insert(name.to_string(), path);
+                }
+            }
+        }
+    }
+    programs
+}
+
+fn path_dirs() -> Vec<PathBuf> {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+fn executable_name(program: &str) -> String {
+    format!("{}{}", program, env::consts::EXE_SUFFIX)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -1,6 +1,8 @@
 // src/project.rs
+use crate::target::{deps_dir, Target};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(Debug, Deserialize)]
 pub struct ProjectsFile {
@@ -26,10 +28,12 @@ pub struct Project {
     pub additional_include_dirs: Vec<String>,
     #[serde(default)]
     pub preprocessor_definitions: Vec<String>,
+    #[serde(flatten)]
+    pub options: CompilerOptions,
     #[serde(default)]
-    pub compiler_flags: Vec<String>,
+    pub compiler_flags: ToolchainFlags,
     #[serde(default)]
-    pub linker_flags: Vec<String>,
+    pub linker_flags: ToolchainFlags,
     #[serde(default = "default_configurations")]
     pub configurations: Vec<String>,
     #[serde(default = "default_platforms")]
@@ -38,9 +42,349 @@ pub struct Project {
     pub character_set: String, // e.g., "Unicode" or "MultiByte"
     #[serde(default)]
     pub output_name: Option<String>,
+    #[serde(default)]
+    pub registry: Option<String>, // Registry name from config.toml, "default" if unset
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    #[serde(default)]
+    pub per_configuration: HashMap<String, ConfigurationSettings>, // Keyed by configuration name
+    #[serde(default)]
+    pub custom_commands: Vec<CustomCommand>,
+    #[serde(default)]
+    pub embed: Vec<EmbedResources>,
+    #[serde(default)]
+    pub config_header: Option<ConfigHeader>,
+    #[serde(default)]
+    pub version: Option<String>, // Semantic version, e.g. "1.4.2"
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub license: Option<String>, // SPDX expression, e.g. "MIT OR Apache-2.0"
+    #[serde(skip)]
+    pub target: Option<Target>, // Set from `--target`; None builds for the host
 }
 
+/// Portable settings that `Compiler` translates for each toolchain. Unset
+/// values take per-configuration defaults; see `Project::options_for`.
 #[derive(Debug, Deserialize, Default)]
+pub struct CompilerOptions {
+    #[serde(default)]
+    pub warnings: Option<Warnings>,
+    #[serde(default)]
+    pub warnings_as_errors: bool,
+    #[serde(default)]
+    pub optimize: Option<Optimize>,
+    #[serde(default)]
+    pub debug_info: Option<bool>,
+    #[serde(default)]
+    pub exceptions: Option<bool>, // C++ only
+    #[serde(default)]
+    pub rtti: Option<bool>, // C++ only
+    #[serde(default)]
+    pub pic: Option<bool>,
+    #[serde(default)]
+    pub lto: Option<bool>, // Unset leaves MSBuild's Release default of whole-program optimization
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Warnings {
+    None,
+    Default, // Whatever the compiler does without flags
+    All,
+    Extra,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Optimize {
+    None,
+    Speed,
+    Size,
+}
+
+/// `CompilerOptions` with every default filled in for one configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildOptions {
+    pub warnings: Warnings,
+    pub warnings_as_errors: bool,
+    pub optimize: Optimize,
+    pub debug_info: bool,
+    pub exceptions: bool,
+    pub rtti: bool,
+    pub pic: bool,
+    pub lto: bool,
+    pub is_cpp: bool,
+}
+
+/// Raw flags, either for every toolchain or keyed by family (`gcc`, `clang`,
+/// `msvc`, `other`) with `all` applying everywhere. A single string is split
+/// on whitespace.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ToolchainFlags {
+    Line(String),
+    Common(Vec<String>),
+    PerToolchain(HashMap<String, Vec<String>>),
+}
+
+impl Default for ToolchainFlags {
+    fn default() -> Self {
+        ToolchainFlags::Common(Vec::new())
+    }
+}
+
+impl ToolchainFlags {
+    pub fn for_family(&self, family: &str) -> Vec<&str> {
+        match self {
+            ToolchainFlags::Line(flags) => flags.split_whitespace().collect(),
+            ToolchainFlags::Common(flags) => flags.iter().map(String::as_str).collect(),
+            ToolchainFlags::PerToolchain(flags) => ["all", family]
+                .iter()
+                .filter_map(|key| flags.get(*key))
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+        }
+    }
+}
+
+/// A `config.h` holding the project's defines plus the results of compiler checks.
+#[derive(Debug, Deserialize)]
+pub struct ConfigHeader {
+    #[serde(default = "default_config_header_path")]
+    pub path: String, // Relative to the project directory
+    #[serde(default)]
+    pub has_header: Vec<String>,
+    #[serde(default)]
+    pub has_function: Vec<String>,
+    #[serde(default)]
+    pub has_symbol: Vec<SymbolCheck>,
+    #[serde(default)]
+    pub sizeof: Vec<String>,
+    #[serde(default)]
+    pub compiles: Vec<CompileCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SymbolCheck {
+    pub symbol: String,
+    pub header: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompileCheck {
+    pub name: String, // Macro defined when `code` compiles
+    pub code: String,
+}
+
+/// Files baked into a generated C source and header, one array per file.
+#[derive(Debug, Deserialize)]
+pub struct EmbedResources {
+    pub files: Vec<String>, // Paths or globs relative to the project directory
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_embed_name")]
+    pub name: String, // Stem of the generated files in generated/
+}
+
+/// A code generation step. Paths are relative to the project directory.
+#[derive(Debug, Deserialize)]
+pub struct CustomCommand {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub command: String, // May use {input}, {inputs}, {output} and {outputs}
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl CustomCommand {
+    pub fn expanded_command(&self) -> String {
+        let first = |paths: &[String]| paths.first().cloned().unwrap_or_default();
+        self.command
+            .replace("{inputs}", &self.inputs.join(" "))
+            .replace("{outputs}", &self.outputs.join(" "))
+            .replace("{input}", &first(&self.inputs))
+            .replace("{output}", &first(&self.outputs))
+    }
+
+    pub fn message(&self) -> String {
+        self.description
+            .clone()
+            .unwrap_or_else(|| format!("Generating {}", self.outputs.join(", ")))
+    }
+}
+
+/// Settings that apply to one configuration only, added to the project-wide ones.
+#[derive(Debug, Deserialize, Default)]
+pub struct ConfigurationSettings {
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    #[serde(default)]
+    pub post_build: Vec<String>,
+}
+
+pub const GENERATED_DIR: &str = "generated";
+pub const SOURCE_EXTENSIONS: [&str; 5] = ["c", "cc", "cpp", "cxx", "c++"];
+pub const HEADER_EXTENSIONS: [&str; 5] = ["h", "hh", "hpp", "hxx", "inl"];
+
+impl Project {
+    /// Custom command outputs that need compiling.
+    pub fn generated_sources(&self) -> Vec<&str> {
+        self.custom_commands
+            .iter()
+            .flat_map(|c| &c.outputs)
+            .filter(|output| has_extension(output, &SOURCE_EXTENSIONS))
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn generated_headers(&self) -> Vec<&str> {
+        self.custom_commands
+            .iter()
+            .flat_map(|c| &c.outputs)
+            .filter(|output| has_extension(output, &HEADER_EXTENSIONS))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Directories holding generated headers, to add to the include path.
+    pub fn generated_include_dirs(&self) -> Vec<String> {
+        let mut dirs = Vec::new();
+        let config_header = self.config_header_path();
+        let written_by_cpm = config_header.into_iter().chain(self.version_header_path());
+        for header in self
+            .generated_headers()
+            .into_iter()
+            .map(String::from)
+            .chain(written_by_cpm)
+        {
+            let dir = Path::new(&header)
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .filter(|p| !p.is_empty())
+                .unwrap_or_else(|| ".".to_string());
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+
+    /// The portable options for `configuration`. Debug configurations default
+    /// to no optimization with debug info, everything else to optimizing for
+    /// speed; shared libraries default to position-independent code.
+    pub fn options_for(&self, configuration: &str) -> BuildOptions {
+        let debug = configuration.eq_ignore_ascii_case("debug");
+        let options = &self.options;
+        BuildOptions {
+            warnings: options.warnings.unwrap_or(Warnings::All),
+            warnings_as_errors: options.warnings_as_errors,
+            optimize: options.optimize.unwrap_or(if debug {
+                Optimize::None
+            } else {
+                Optimize::Speed
+            }),
+            debug_info: options.debug_info.unwrap_or(debug),
+            exceptions: options.exceptions.unwrap_or(true),
+            rtti: options.rtti.unwrap_or(true),
+            pic: options.pic.unwrap_or(self.project_type == "SharedLib"),
+            lto: options.lto.unwrap_or(false),
+            is_cpp: self.language.starts_with("C++"),
+        }
+    }
+
+    /// `structure.output_dir`, with a subdirectory per target when cross-compiling.
+    pub fn output_dir(&self) -> String {
+        match &self.target {
+            Some(target) => format!("{}/{}", self.structure.output_dir, target.triple),
+            None => self.structure.output_dir.clone(),
+        }
+    }
+
+    /// The file name of the project's output without prefix or extension:
+    /// `output_name`, else the last component of the name, so `libs/net`
+    /// builds `libnet.a`.
+    pub fn output_stem(&self) -> &str {
+        self.output_name
+            .as_deref()
+            .unwrap_or_else(|| self.name.rsplit('/').next().unwrap_or(&self.name))
+    }
+
+    /// `Console` and `Windowed` projects build executables.
+    pub fn is_library(&self) -> bool {
+        matches!(self.project_type.as_str(), "StaticLib" | "SharedLib")
+    }
+
+    /// The installed packages for this project's target, relative to the workspace.
+    pub fn deps_dir(&self) -> String {
+        deps_dir(self.target.as_ref().map(|t| t.triple.as_str()))
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Probe results differ between targets, so a cross build's config header
+    /// lives under its output directory.
+    pub fn config_header_path(&self) -> Option<String> {
+        let config_header = self.config_header.as_ref()?;
+        match &self.target {
+            Some(_) => Some(format!("{}/{}", self.output_dir(), config_header.path)),
+            None => Some(config_header.path.clone()),
+        }
+    }
+
+    /// The manifest `version`, which `parser` has already checked is valid.
+    pub fn semantic_version(&self) -> Option<semver::Version> {
+        self.version
+            .as_deref()
+            .and_then(|v| semver::Version::parse(v).ok())
+    }
+
+    /// `generated/<name>_version.h`, written when the project has a version.
+    pub fn version_header_path(&self) -> Option<String> {
+        self.version
+            .as_ref()
+            .map(|_| format!("{}/{}_version.h", GENERATED_DIR, self.name))
+    }
+
+    /// The configuration built when none is selected: the first one listed.
+    pub fn default_configuration(&self) -> &str {
+        self.configurations
+            .first()
+            .map(String::as_str)
+            .unwrap_or("Debug")
+    }
+
+    pub fn pre_build_commands(&self, configuration: &str) -> Vec<&str> {
+        let mut commands = self
+            .pre_build
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if let Some(settings) = self.per_configuration.get(configuration) {
+            commands.extend(settings.pre_build.iter().map(String::as_str));
+        }
+        commands
+    }
+
+    pub fn post_build_commands(&self, configuration: &str) -> Vec<&str> {
+        let mut commands = self
+            .post_build
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if let Some(settings) = self.per_configuration.get(configuration) {
+            commands.extend(settings.post_build.iter().map(String::as_str));
+        }
+        commands
+    }
+}
+
+#[derive(Debug, Deserialize)]
 pub struct ProjectStructure {
     #[serde(default = "default_src_dir")]
     pub src_dir: String,
@@ -50,6 +394,32 @@ pub struct ProjectStructure {
     pub output_dir: String,
 }
 
+// Written out so a missing [projects.structure] table gets the same defaults as a partial one
+impl Default for ProjectStructure {
+    fn default() -> Self {
+        ProjectStructure {
+            src_dir: default_src_dir(),
+            include_dir: default_include_dir(),
+            output_dir: default_output_dir(),
+        }
+    }
+}
+
+fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn default_config_header_path() -> String {
+    "generated/config.h".to_string()
+}
+
+fn default_embed_name() -> String {
+    "resources".to_string()
+}
+
 fn default_src_dir() -> String {
     "src".to_string()
 }
@@ -77,3 +447,88 @@ fn default_platforms() -> Vec<String> {
 fn default_character_set() -> String {
     "Unicode".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(fields: &str) -> Project {
+        let manifest = format!(
+            "[[projects]]\nname = \"app\"\ntype = \"Console\"\n\
+             compiler = \"gcc\"\nlanguage = \"C++17\"\n{}",
+            fields
+        );
+        toml::from_str::<ProjectsFile>(&manifest)
+            .unwrap()
+            .projects
+            .remove(0)
+    }
+
+    #[test]
+    fn compiler_flags_as_a_list() {
+        let project = project("compiler_flags = [\"-O3\", \"-march=native\"]");
+        assert_eq!(
+            project.compiler_flags.for_family("gcc"),
+            ["-O3", "-march=native"]
+        );
+        assert_eq!(
+            project.compiler_flags.for_family("msvc"),
+            ["-O3", "-march=native"]
+        );
+        assert!(project.linker_flags.for_family("gcc").is_empty());
+    }
+
+    #[test]
+    fn compiler_flags_as_a_string() {
+        let project = project(
+            "compiler_flags = \"-O3  -march=native\"\n\
+                               linker_flags = \"-static\"",
+        );
+        assert_eq!(
+            project.compiler_flags.for_family("gcc"),
+            ["-O3", "-march=native"]
+        );
+        assert_eq!(project.linker_flags.for_family("clang"), ["-static"]);
+    }
+
+    #[test]
+    fn compiler_flags_per_toolchain() {
+        let project = project(
+            "[projects.compiler_flags]\nall = [\"-DX\"]\n\
+             gcc = [\"-fno-strict-aliasing\"]\nmsvc = [\"/utf-8\"]",
+        );
+        assert_eq!(
+            project.compiler_flags.for_family("gcc"),
+            ["-DX", "-fno-strict-aliasing"]
+        );
+        assert_eq!(project.compiler_flags.for_family("msvc"), ["-DX", "/utf-8"]);
+        assert_eq!(project.compiler_flags.for_family("clang"), ["-DX"]);
+    }
+
+    #[test]
+    fn options_sit_beside_the_other_fields() {
+        let project = project(
+            "warnings = \"extra\"\noptimize = \"size\"\nrtti = false\nlto = true\n\
+             compiler_flags = [\"-g3\"]",
+        );
+        let release = project.options_for("Release");
+        assert_eq!(release.warnings, Warnings::Extra);
+        assert_eq!(release.optimize, Optimize::Size);
+        assert!(!release.rtti);
+        assert!(release.lto);
+        assert_eq!(project.compiler_flags.for_family("gcc"), ["-g3"]);
+    }
+
+    #[test]
+    fn unset_options_take_configuration_defaults() {
+        let project = project("");
+        let debug = project.options_for("Debug");
+        let release = project.options_for("Release");
+        assert_eq!(debug.optimize, Optimize::None);
+        assert!(debug.debug_info);
+        assert_eq!(release.optimize, Optimize::Speed);
+        assert!(!release.debug_info);
+        assert!(!release.lto);
+        assert_eq!(project.options.lto, None);
+    }
+}
@@ -0,0 +1,126 @@
+// src/registry_index.rs
+use crate::config::Registry;
+use crate::download::Downloader;
+use semver::{BuildMetadata, Op, Version, VersionReq};
+use serde::Deserialize;
+
+/// `<registry>/<package>/index.json`, listing every published version.
+#[derive(Debug, Deserialize)]
+struct IndexFile {
+    versions: Vec<String>,
+}
+
+pub struct PackageIndex {
+    pub versions: Vec<Version>, // Ascending
+}
+
+impl PackageIndex {
+    pub fn fetch(
+        downloader: &Downloader,
+        registry: &Registry,
+        package: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = format!("{}/{}/index.json", registry.url, package);
+        let content = downloader
+            .fetch_text(&url, registry.token.as_deref())
+            .map_err(|e| {
+                format!(
+                    "Package {} not found in registry {}: {}",
+                    package, registry.name, e
+                )
+            })?;
+        let index: IndexFile = serde_json::from_str(&content)
+            .map_err(|e| format!("Malformed index for {}: {}", package, e))?;
+
+        // Skip entries that aren't semver rather than failing the whole package
+        let mut versions = index
+            .versions
+            .iter()
+            .filter_map(|v| Version::parse(v).ok())
+            .collect::<Vec<_>>();
+        versions.sort();
+        Ok(PackageIndex { versions })
+    }
+
+    pub fn newest_matching(&self, requirements: &[VersionReq]) -> Option<&Version> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|v| requirements.iter().all(|req| req.matches(v)))
+    }
+
+    /// Newest release, ignoring pre-releases.
+    pub fn latest(&self) -> Option<&Version> {
+        self.versions.iter().rev().find(|v| v.pre.is_empty())
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        self.versions.contains(version)
+    }
+}
+
+/// Parses a manifest requirement. A complete version on its own (`1.2.3`)
+/// stays exact, as it was before requirements were supported; anything else
+/// follows semver, so `10.1` means `^10.1`.
+pub fn parse_requirement(requirement: &str) -> Result<VersionReq, semver::Error> {
+    match Version::parse(requirement.trim()) {
+        Ok(version) => VersionReq::parse(&format!("={}", version)),
+        Err(_) => VersionReq::parse(requirement),
+    }
+}
+
+/// The one version `requirements` allow when one of them is exact. It can be
+/// installed without an index.
+pub fn exact_version(requirements: &[VersionReq]) -> Option<Version> {
+    let version = requirements
+        .iter()
+        .find_map(|req| match req.comparators.as_slice() {
+            [comparator] if comparator.op == Op::Exact => Some(Version {
+                major: comparator.major,
+                minor: comparator.minor?,
+                patch: comparator.patch?,
+                pre: comparator.pre.clone(),
+                build: BuildMetadata::EMPTY,
+            }),
+            _ => None,
+        })?;
+    requirements
+        .iter()
+        .all(|req| req.matches(&version))
+        .then_some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bare_version_is_an_exact_requirement() {
+        assert_eq!(parse_requirement("1.2.3").unwrap().to_string(), "=1.2.3");
+        assert_eq!(parse_requirement(" 1.2.3 ").unwrap().to_string(), "=1.2.3");
+        assert_eq!(parse_requirement("10.1").unwrap().to_string(), "^10.1");
+        assert_eq!(parse_requirement("^1.2").unwrap().to_string(), "^1.2");
+        assert_eq!(
+            parse_requirement(">=1.0, <2").unwrap().to_string(),
+            ">=1.0, <2"
+        );
+        assert!(parse_requirement("one").is_err());
+    }
+
+    #[test]
+    fn exact_version_needs_every_requirement_to_agree() {
+        let requirements = |reqs: &[&str]| {
+            reqs.iter()
+                .map(|r| parse_requirement(r).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            exact_version(&requirements(&["1.2.3", "^1.2"])),
+            Some(Version::new(1, 2, 3))
+        );
+        assert_eq!(exact_version(&requirements(&["1.2.3", "^2"])), None);
+        assert_eq!(exact_version(&requirements(&["^1.2", ">=1.0"])), None);
+        // `=1.2` is a range, not one version
+        assert_eq!(exact_version(&requirements(&["=1.2"])), None);
+    }
+}
@@ -0,0 +1,235 @@
+// src/scaffold.rs
+use crate::config::cpm_home;
+use crate::manifest_editor::ManifestEditor;
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+pub const PROJECT_TYPES: [&str; 4] = ["Console", "Windowed", "StaticLib", "SharedLib"];
+pub const LANGUAGES: [&str; 10] = [
+    "C89", "C99", "C11", "C17", "C++98", "C++11", "C++14", "C++17", "C++20", "C++23",
+];
+
+const GITIGNORE: &str = "build/\ndeps/\n";
+
+pub struct ScaffoldOptions<'a> {
+    pub name: &'a str,
+    pub project_type: &'a str,
+    pub language: &'a str,
+    // A directory, or the name of one under ~/.cpm/templates
+    pub template: Option<&'a str>,
+}
+
+/// `cpm new`: creates a workspace directory holding a manifest, a `.gitignore`
+/// and the project itself in `<name>/<name>`, which is where the generators
+/// look for it.
+pub fn new_workspace(options: &ScaffoldOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let workspace_dir = Path::new(options.name);
+    if workspace_dir.exists() {
+        return Err(format!("{} already exists", workspace_dir.display()).into());
+    }
+    let template = resolve_template(options.template)?;
+    fs::create_dir_all(workspace_dir)?;
+
+    let mut projects = toml_edit::ArrayOfTables::new();
+    projects.push(project_entry(options));
+    let mut document = DocumentMut::new();
+    document.insert("projects", Item::ArrayOfTables(projects));
+    fs::write(workspace_dir.join("project.toml"), document.to_string())?;
+    fs::write(workspace_dir.join(".gitignore"), GITIGNORE)?;
+
+    write_project_files(
+        &workspace_dir.join(options.name),
+        options,
+        template.as_deref(),
+    )?;
+    info!("Created workspace {}", options.name);
+    Ok(())
+}
+
+/// `cpm init`: adds a project to an existing workspace manifest and writes its
+/// starter files next to the manifest.
+pub fn init_project(
+    manifest_path: &str,
+    options: &ScaffoldOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = Path::new(manifest_path);
+    if !manifest.exists() {
+        return Err(format!(
+            "{} not found; use `cpm new` to start a workspace",
+            manifest_path
+        )
+        .into());
+    }
+    let workspace_dir = manifest.parent().unwrap_or(Path::new(""));
+    let project_dir = workspace_dir.join(options.name);
+    if project_dir.exists() {
+        return Err(format!("{} already exists", project_dir.display()).into());
+    }
+    let template = resolve_template(options.template)?;
+
+    let mut editor = ManifestEditor::open(manifest)?;
+    editor.add_project(options.name, project_entry(options))?;
+    write_project_files(&project_dir, options, template.as_deref())?;
+    editor.save()?;
+    info!("Added project {} to {}", options.name, manifest_path);
+    Ok(())
+}
+
+fn project_entry(options: &ScaffoldOptions) -> Table {
+    let (compiler, build_system) = if cfg!(windows) {
+        ("MSVC", "VisualStudio")
+    } else if options.language.starts_with("C++") {
+        ("g++", "Makefile")
+    } else {
+        ("gcc", "Makefile")
+    };
+    let mut build_systems = Array::new();
+    build_systems.push(build_system);
+
+    let mut table = Table::new();
+    table.insert("name", value(options.name));
+    table.insert("type", value(options.project_type));
+    table.insert("compiler", value(compiler));
+    table.insert("language", value(options.language));
+    table.insert("build_systems", value(build_systems));
+    table
+}
+
+fn resolve_template(template: Option<&str>) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let template = match template {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let direct = PathBuf::from(template);
+    if direct.is_dir() {
+        return Ok(Some(direct));
+    }
+    let installed = cpm_home().join("templates").join(template);
+    if installed.is_dir() {
+        return Ok(Some(installed));
+    }
+    Err(format!(
+        "Template '{}' is neither a directory nor installed in {}",
+        template,
+        cpm_home().join("templates").display()
+    )
+    .into())
+}
+
+fn write_project_files(
+    project_dir: &Path,
+    options: &ScaffoldOptions,
+    template: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let files = match template {
+        Some(template) => read_template_dir(template, template)?,
+        None => builtin_template(options),
+    };
+    for (relative_path, content) in files {
+        let path = project_dir.join(substitute(&relative_path, options));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, substitute(&content, options))?;
+    }
+    Ok(())
+}
+
+fn read_template_dir(
+    root: &Path,
+    dir: &Path,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(read_template_dir(root, &path)?);
+        } else {
+            let relative = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let content =
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            files.push((relative, content));
+        }
+    }
+    Ok(files)
+}
+
+/// Replaces `{{name}}`, `{{ident}}` (the name as a C identifier), `{{NAME}}`
+/// (upper-cased, for macros), `{{type}}`, `{{language}}`, `{{ext}}` (`c` or
+/// `cpp`) and `{{header_ext}}`.
+fn substitute(text: &str, options: &ScaffoldOptions) -> String {
+    let is_cpp = options.language.starts_with("C++");
+    let identifier = identifier(options.name);
+    text.replace("{{name}}", options.name)
+        .replace("{{ident}}", &identifier)
+        .replace("{{NAME}}", &identifier.to_ascii_uppercase())
+        .replace("{{type}}", options.project_type)
+        .replace("{{language}}", options.language)
+        .replace("{{ext}}", if is_cpp { "cpp" } else { "c" })
+        .replace("{{header_ext}}", if is_cpp { "hpp" } else { "h" })
+}
+
+/// `name` with everything but letters, digits and `_` replaced, so `my-lib`
+/// becomes `my_lib`.
+fn identifier(name: &str) -> String {
+    let mut identifier = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+fn builtin_template(options: &ScaffoldOptions) -> Vec<(String, String)> {
+    let is_cpp = options.language.starts_with("C++");
+    if options.project_type == "Windowed" {
+        // The Windows subsystem starts at WinMain; elsewhere it's a plain executable
+        let main = "#ifdef _WIN32\n#include <windows.h>\n\nint WINAPI WinMain(HINSTANCE instance, HINSTANCE previous, LPSTR command_line, int show) {\n    (void)instance;\n    (void)previous;\n    (void)command_line;\n    (void)show;\n    MessageBoxA(NULL, \"Hello from {{name}}!\", \"{{name}}\", MB_OK);\n    return 0;\n}\n#else\n#include <stdio.h>\n\nint main(void) {\n    printf(\"Hello from {{name}}!\\n\");\n    return 0;\n}\n#endif\n";
+        return vec![("src/main.{{ext}}".to_string(), main.to_string())];
+    }
+    if options.project_type == "Console" {
+        let main = if is_cpp {
+            "#include <iostream>\n\nint main() {\n    std::cout << \"Hello from {{name}}!\" << std::endl;\n    return 0;\n}\n"
+        } else {
+            "#include <stdio.h>\n\nint main(void) {\n    printf(\"Hello from {{name}}!\\n\");\n    return 0;\n}\n"
+        };
+        return vec![("src/main.{{ext}}".to_string(), main.to_string())];
+    }
+
+    // Libraries get a header with an export macro and a matching implementation
+    let header = if is_cpp {
+        "#pragma once\n\n#include <string>\n\n{{NAME}}_API std::string {{ident}}_greeting();\n"
+    } else {
+        "#ifndef {{NAME}}_H\n#define {{NAME}}_H\n\n#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n{{NAME}}_API const char *{{ident}}_greeting(void);\n\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n"
+    };
+    let export = if options.project_type == "SharedLib" {
+        "#if defined(_WIN32)\n#  if defined({{NAME}}_BUILD)\n#    define {{NAME}}_API __declspec(dllexport)\n#  else\n#    define {{NAME}}_API __declspec(dllimport)\n#  endif\n#else\n#  define {{NAME}}_API __attribute__((visibility(\"default\")))\n#endif\n\n"
+    } else {
+        "#define {{NAME}}_API\n\n"
+    };
+    let header = match header.split_once("\n\n") {
+        Some((guard, rest)) => format!("{}\n\n{}{}", guard, export, rest),
+        None => header.to_string(),
+    };
+    let source = if is_cpp {
+        "#define {{NAME}}_BUILD\n#include \"{{name}}/{{name}}.{{header_ext}}\"\n\nstd::string {{ident}}_greeting() {\n    return \"Hello from {{name}}!\";\n}\n"
+    } else {
+        "#define {{NAME}}_BUILD\n#include \"{{name}}/{{name}}.{{header_ext}}\"\n\nconst char *{{ident}}_greeting(void) {\n    return \"Hello from {{name}}!\";\n}\n"
+    };
+    vec![
+        (
+            "include/{{name}}/{{name}}.{{header_ext}}".to_string(),
+            header,
+        ),
+        ("src/{{name}}.{{ext}}".to_string(), source.to_string()),
+    ]
+}
@@ -0,0 +1,79 @@
+// src/target.rs
+use crate::config::{Config, ToolchainConfig};
+use std::path::PathBuf;
+
+const DEPS_DIR: &str = "deps";
+
+/// The platform selected with `--target`, with the toolchain configured for it.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub triple: String,
+    pub toolchain: ToolchainConfig,
+}
+
+impl Target {
+    /// Looks `name` up as a toolchain name, then as a toolchain's triple, and
+    /// otherwise takes it as a bare triple whose tools are found by prefix.
+    pub fn resolve(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = Config::load()?;
+        let configured = config.toolchains.get(name).map(|t| (name, t)).or_else(|| {
+            config
+                .toolchains
+                .iter()
+                .find(|(_, t)| t.target.as_deref() == Some(name))
+                .map(|(n, t)| (n.as_str(), t))
+        });
+        if let Some((toolchain_name, toolchain)) = configured {
+            return Ok(Target {
+                triple: toolchain
+                    .target
+                    .clone()
+                    .unwrap_or_else(|| toolchain_name.to_string()),
+                toolchain: toolchain.clone(),
+            });
+        }
+        if name.split('-').count() < 2 {
+            return Err(format!(
+                "'{}' is neither a toolchain in config.toml nor a target triple",
+                name
+            )
+            .into());
+        }
+        Ok(Target {
+            triple: name.to_string(),
+            toolchain: ToolchainConfig {
+                target: Some(name.to_string()),
+                cc: None,
+                cxx: None,
+                ar: None,
+                sysroot: None,
+                compiler_flags: Vec::new(),
+                linker_flags: Vec::new(),
+            },
+        })
+    }
+
+    /// The architecture part of the triple, normalized so that e.g.
+    /// `armv7-unknown-linux-gnueabihf` and `arm-linux-gnueabihf` compare equal.
+    pub fn architecture(triple: &str) -> String {
+        let arch = triple.split('-').next().unwrap_or_default();
+        match arch {
+            a if a.starts_with("arm") && a != "arm64" || a.starts_with("thumb") => {
+                "arm".to_string()
+            }
+            "arm64" => "aarch64".to_string(),
+            "amd64" | "x64" => "x86_64".to_string(),
+            "i386" | "i486" | "i586" | "i686" | "x86" => "x86".to_string(),
+            a => a.to_string(),
+        }
+    }
+}
+
+/// Where packages are installed: `deps/` for the host and `deps/<triple>/`
+/// when cross-compiling, relative to the workspace.
+pub fn deps_dir(triple: Option<&str>) -> PathBuf {
+    match triple {
+        Some(triple) => PathBuf::from(DEPS_DIR).join(triple),
+        None => PathBuf::from(DEPS_DIR),
+    }
+}
@@ -0,0 +1,79 @@
+// src/version_header.rs
+use crate::config_header::macro_name;
+use crate::project::Project;
+use log::info;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Writes `generated/<name>_version.h` with the version split into
+/// major/minor/patch and, inside a git checkout, the commit it was built from.
+/// Like `config.h` it is only rewritten when the content changes.
+pub fn write_version_header(project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    let (version, header_path) = match (project.semantic_version(), project.version_header_path()) {
+        (Some(version), Some(path)) => (version, path),
+        _ => return Ok(()),
+    };
+    let project_dir = Path::new(&project.name);
+    let name = macro_name(&project.name);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/* Generated by cpm from the project version. Do not edit. */"
+    )?;
+    writeln!(out, "#ifndef {}_VERSION_H", name)?;
+    writeln!(out, "#define {}_VERSION_H\n", name)?;
+    writeln!(out, "#define {}_VERSION \"{}\"", name, version)?;
+    writeln!(out, "#define {}_VERSION_MAJOR {}", name, version.major)?;
+    writeln!(out, "#define {}_VERSION_MINOR {}", name, version.minor)?;
+    writeln!(out, "#define {}_VERSION_PATCH {}", name, version.patch)?;
+    match git_state(project_dir) {
+        Some((commit, dirty)) => {
+            writeln!(out, "#define {}_GIT_COMMIT \"{}\"", name, commit)?;
+            writeln!(
+                out,
+                "#define {}_GIT_DIRTY {}",
+                name,
+                if dirty { 1 } else { 0 }
+            )?;
+        }
+        None => {
+            writeln!(out, "/* #undef {}_GIT_COMMIT */", name)?;
+            writeln!(out, "/* #undef {}_GIT_DIRTY */", name)?;
+        }
+    }
+    writeln!(out, "\n#endif")?;
+
+    let header_path = project_dir.join(header_path);
+    if fs::read_to_string(&header_path).ok().as_deref() == Some(out.as_str()) {
+        return Ok(());
+    }
+    if let Some(parent) = header_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&header_path, out)?;
+    info!("Wrote {}", header_path.display());
+    Ok(())
+}
+
+/// The short commit hash and whether tracked files have uncommitted changes,
+/// or `None` outside a git checkout or without git installed.
+fn git_state(dir: &Path) -> Option<(String, bool)> {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let commit = git(&["rev-parse", "--short=12", "HEAD"])?;
+    // Untracked files are ignored so generated output doesn't mark every build dirty
+    let dirty = !git(&["status", "--porcelain", "--untracked-files=no"])?.is_empty();
+    Some((commit, dirty))
+}